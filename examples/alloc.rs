@@ -0,0 +1,104 @@
+/*
+----------------------------------------------------------------------------------
+Counts heap allocations per training step for the train.rs network, with the Rc based
+Value, a traced Value graph and the arena Tape. Run with `cargo run --release --example alloc`
+----------------------------------------------------------------------------------
+*/
+use micrograd::engine::{Activations, Value};
+use micrograd::nn::{mlp, Layer};
+use micrograd::stats::CountingAlloc;
+use micrograd::tape::{Tape, Var};
+use micrograd::trace::Trace;
+use std::array::from_fn;
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+mlp!(4);
+
+const STEPS: usize = 100;
+const XS: [[f32; 3]; 4] = [[2.0, 3.0, -1.0], [3.0, -1.0, 0.5], [0.5, 1.0, 1.0], [1.0, 1.0, -1.0]];
+const YS: [f32; 4] = [1.0, -1.0, -1.0, 1.0];
+
+fn main() {
+    let value = with_value();
+    let trace = with_trace();
+    let tape = with_tape();
+    println!("Value: {value} allocations per step");
+    println!("Trace: {trace} allocations per step");
+    println!("Tape:  {tape} allocations per step");
+}
+
+fn with_value() -> usize {
+    let n: MLP<3, 4, 4, 1> = MLP::new(Activations::Tanh, Activations::Tanh, Activations::Linear);
+    let before = CountingAlloc::count();
+    for _ in 0..STEPS {
+        let loss: Value = XS
+            .iter()
+            .zip(YS)
+            .map(|(x, y)| (&n.forward(&x.map(Value::from))[0] - &Value::from(y)).pow(&2.0.into()))
+            .sum();
+        n.parameters().for_each(|p| p.zero_grad());
+        loss.backward();
+        n.parameters().for_each(|p| p.adjust(-0.01));
+    }
+    (CountingAlloc::count() - before).allocations / STEPS
+}
+
+// The Value graph built once and replayed
+fn with_trace() -> usize {
+    let n: MLP<3, 4, 4, 1> = MLP::new(Activations::Tanh, Activations::Tanh, Activations::Linear);
+    let (xs, ys) = (XS.map(|x| x.map(Value::from)), YS.map(Value::from));
+    let loss: Value = xs.iter().zip(&ys).map(|(x, y)| (&n.forward(x)[0] - y).pow(&2.0.into())).sum();
+    let slots: Vec<&Value> = xs.iter().flatten().chain(&ys).collect();
+    let mut trace = Trace::new(&loss, &slots, n.parameters());
+    let data: Vec<f32> = XS.iter().flatten().chain(&YS).copied().collect();
+
+    let before = CountingAlloc::count();
+    for _ in 0..STEPS {
+        trace.forward(&data);
+        n.parameters().for_each(|p| p.zero_grad());
+        trace.backward();
+        n.parameters().for_each(|p| p.adjust(-0.01));
+    }
+    (CountingAlloc::count() - before).allocations / STEPS
+}
+
+// Same 3 -> 4 -> 4 -> 1 network, the weights live at the start of the tape
+fn with_tape() -> usize {
+    fn layer<'t, const P: usize, const N: usize>(w: &[[Var<'t>; P]; N], b: &[Var<'t>; N], x: [Var<'t>; P], tanh: bool) -> [Var<'t>; N] {
+        from_fn(|i| {
+            let act = w[i].iter().zip(x).map(|(w, x)| *w * x).sum::<Var>() + b[i];
+            if tanh {
+                act.tanh()
+            } else {
+                act
+            }
+        })
+    }
+
+    let tape: Tape = Tape::new();
+    let rand = |_| tape.var(rand::random::<f32>() * 2.0 - 1.0);
+    let (w1, b1): ([[Var; 3]; 4], [Var; 4]) = (from_fn(|_| from_fn(rand)), from_fn(|_| tape.var(0.0)));
+    let (w2, b2): ([[Var; 4]; 4], [Var; 4]) = (from_fn(|_| from_fn(rand)), from_fn(|_| tape.var(0.0)));
+    let (w3, b3): ([[Var; 4]; 1], [Var; 1]) = (from_fn(|_| from_fn(rand)), from_fn(|_| tape.var(0.0)));
+    let params = tape.len();
+
+    let before = CountingAlloc::count();
+    for _ in 0..STEPS {
+        tape.truncate(params);
+        let loss: Var = XS
+            .iter()
+            .zip(YS)
+            .map(|(x, y)| {
+                let h = layer(&w2, &b2, layer(&w1, &b1, x.map(|x| tape.var(x)), true), true);
+                (layer(&w3, &b3, h, false)[0] - tape.var(y)).pow(tape.var(2.0))
+            })
+            .sum();
+        tape.zero_grad();
+        loss.backward();
+        let weights = w1.iter().flatten().chain(w2.iter().flatten()).chain(w3.iter().flatten());
+        weights.chain(&b1).chain(&b2).chain(&b3).for_each(|p| p.adjust(-0.01));
+    }
+    (CountingAlloc::count() - before).allocations / STEPS
+}
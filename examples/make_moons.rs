@@ -1,64 +1,64 @@
-use csv;
 use kdam::{tqdm, BarExt};
 use micrograd::engine::{Activations, Value};
 //use micrograd::mlp;
 use micrograd::nn::{mlp, Layer};
+use micrograd::stats::CountingAlloc;
+use std::error::Error;
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
 
 // Initialize model size
 mlp!(4);
 
-fn main() {
-    let (x, y): (Vec<[f32; 2]>, Vec<f32>) = csv::ReaderBuilder::new()
-        .from_path("./datasets/make_moons/make_moons.csv")
-        .unwrap()
+fn main() -> Result<(), Box<dyn Error>> {
+    let samples = csv::ReaderBuilder::new()
+        .from_path("./datasets/make_moons/make_moons.csv")?
         .records()
         .map(|r| {
-            let record = r.unwrap();
-            let x_val = [
-                record.get(0).unwrap().parse::<f32>().unwrap(),
-                record.get(1).unwrap().parse::<f32>().unwrap(),
-            ];
-            let y_val = record.get(2).unwrap().parse::<f32>().unwrap();
-            (x_val, y_val)
+            let record = r?;
+            let column = |i| record.get(i).ok_or(format!("line {:?} has no column {i}", record.position().map(|p| p.line())));
+            Ok(([column(0)?.parse::<f32>()?, column(1)?.parse::<f32>()?], column(2)?.parse::<f32>()?))
         })
-        .unzip(); // Splits into two vectors
+        .collect::<Result<Vec<([f32; 2], f32)>, Box<dyn Error>>>()?;
+    let (x, y): (Vec<[f32; 2]>, Vec<f32>) = samples.into_iter().unzip(); // Splits into two vectors
 
     let model: MLP<2, 16, 16, 1> = MLP::new(Activations::Relu, Activations::Relu, Activations::Linear);
     //let model = mlp!(2, 16, 16, 1);
 
-    fn loss(xs: &[[f32; 2]], ys: &[f32], model: &MLP<2, 16, 16, 1>) -> Value {
+    fn loss(xs: &[[f32; 2]], ys: &[f32], model: &MLP<2, 16, 16, 1>) -> Result<Value, micrograd::Error> {
         let inputs: Vec<Vec<Value>> = xs.iter().map(|xrow| vec![Value::from(xrow[0]), Value::from(xrow[1])]).collect();
 
         // forward the model to get scores
         let scores: Vec<Value> = inputs
             .iter()
-            .map(|xrow| {
-                let input_array: &[Value; 2] = xrow.as_slice().try_into().expect("Input size mismatch");
-                model.forward(input_array)[0].clone()
-            })
-            .collect();
+            .map(|xrow| Ok(model.try_forward(xrow)?[0].clone()))
+            .collect::<Result<_, micrograd::Error>>()?;
 
         let losses: Vec<Value> = ys
             .iter()
             .zip(&scores)
             .map(|(yi, scorei)| (Value::from(1.0) + &Value::from(-yi) * scorei).relu())
             .collect();
-        let n: f32 = (&losses).len() as f32;
+        let n: f32 = losses.len() as f32;
         let data_loss: Value = losses.into_iter().sum::<Value>() / Value::from(n);
 
         let alpha: f32 = 0.0001;
-        let reg_loss: Value = Value::from(alpha) * model.parameters().map(|p| p * p).into_iter().sum::<Value>();
-        let total_loss = data_loss + reg_loss;
-
-        total_loss
+        let reg_loss: Value = Value::from(alpha) * model.parameters().map(|p| p * p).sum::<Value>();
+        Ok(data_loss + reg_loss)
     }
 
     let range = 150;
     let mut pb = tqdm!(total = range);
     let _ = pb.refresh();
     for k in 0..range {
+        let allocs = CountingAlloc::count();
+
         // forward
-        let total_loss = loss(&x, &y, &model);
+        let total_loss = loss(&x, &y, &model)?;
+        if k == 0 {
+            let _ = pb.write(total_loss.graph_stats().to_string());
+        }
 
         // backward
         model.parameters().for_each(|p| p.zero_grad());
@@ -72,7 +72,9 @@ fn main() {
             *p.data.borrow_mut() -= delta;
         }
 
-        pb.set_description(format!("Loss {:.3}", total_loss.data.borrow()));
+        let allocs = CountingAlloc::count() - allocs;
+        pb.set_description(format!("Loss {:.3}, {} allocations", total_loss.data.borrow(), allocs.allocations));
         let _ = pb.update(1);
     }
+    Ok(())
 }
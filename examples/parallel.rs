@@ -0,0 +1,83 @@
+/*
+----------------------------------------------------------------------------------
+make_moons again, but the per-sample forward and backward is split across threads.
+Every thread backpropagates its own slice of the loss and the gradients meet in the
+shared parameters. Run with `cargo run --release --example parallel --features sync`
+----------------------------------------------------------------------------------
+*/
+use kdam::{tqdm, BarExt};
+use micrograd::engine::{Activations, Value};
+use micrograd::nn::{mlp, Layer};
+use std::{error::Error, thread};
+
+// Initialize model size
+mlp!(4);
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let samples = csv::ReaderBuilder::new()
+        .from_path("./datasets/make_moons/make_moons.csv")?
+        .records()
+        .map(|r| {
+            let record = r?;
+            let column = |i| record.get(i).ok_or(format!("line {:?} has no column {i}", record.position().map(|p| p.line())));
+            Ok(([column(0)?.parse::<f32>()?, column(1)?.parse::<f32>()?], column(2)?.parse::<f32>()?))
+        })
+        .collect::<Result<Vec<([f32; 2], f32)>, Box<dyn Error>>>()?;
+    let (x, y): (Vec<[f32; 2]>, Vec<f32>) = samples.into_iter().unzip(); // Splits into two vectors
+
+    let model: MLP<2, 16, 16, 1> = MLP::new(Activations::Relu, Activations::Relu, Activations::Linear);
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+    let chunk = x.len().div_ceil(threads);
+    let n = x.len() as f32;
+
+    // Data loss of one slice of the batch, already divided by the full batch size
+    fn loss(xs: &[[f32; 2]], ys: &[f32], n: f32, model: &MLP<2, 16, 16, 1>) -> Value {
+        xs.iter()
+            .zip(ys)
+            .map(|(xrow, yi)| {
+                let score = model.forward(&[Value::from(xrow[0]), Value::from(xrow[1])])[0].clone();
+                (Value::from(1.0) + &Value::from(-yi) * &score).relu()
+            })
+            .sum::<Value>()
+            / Value::from(n)
+    }
+
+    let range = 150;
+    let mut pb = tqdm!(total = range);
+    let _ = pb.refresh();
+    for k in 0..range {
+        model.parameters().for_each(|p| p.zero_grad());
+
+        // forward + backward, one slice per thread
+        let data_loss: f32 = thread::scope(|s| {
+            let handles: Vec<_> = x
+                .chunks(chunk)
+                .zip(y.chunks(chunk))
+                .map(|(xs, ys)| {
+                    let model = &model;
+                    s.spawn(move || {
+                        let partial = loss(xs, ys, n, model);
+                        partial.backward();
+                        partial.data()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum()
+        });
+
+        let alpha: f32 = 0.0001;
+        let reg_loss: Value = Value::from(alpha) * model.parameters().map(|p| p * p).sum::<Value>();
+        reg_loss.backward();
+
+        // update (sgd)
+        let learning_rate = 1.0 - 0.9 * (k as f32) / (range as f32);
+        for p in model.parameters() {
+            let delta = learning_rate * *p.grad.borrow();
+            *p.data.borrow_mut() -= delta;
+        }
+
+        pb.set_description(format!("Loss {:.3}", data_loss + reg_loss.data()));
+        let _ = pb.update(1);
+    }
+    Ok(())
+}
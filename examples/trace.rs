@@ -0,0 +1,65 @@
+/*
+----------------------------------------------------------------------------------
+Times a training step of the train.rs network when the graph is rebuilt every step
+and when it is traced once and replayed, with and without Trace::optimize. Run with
+`cargo run --release --example trace`
+----------------------------------------------------------------------------------
+*/
+use micrograd::engine::{Activations, Value};
+use micrograd::nn::{mlp, Layer};
+use micrograd::trace::Trace;
+use std::time::Instant;
+
+mlp!(4);
+
+const STEPS: u32 = 10000;
+const XS: [[f32; 3]; 4] = [[2.0, 3.0, -1.0], [3.0, -1.0, 0.5], [0.5, 1.0, 1.0], [1.0, 1.0, -1.0]];
+const YS: [f32; 4] = [1.0, -1.0, -1.0, 1.0];
+
+fn loss(n: &MLP<3, 4, 4, 1>, xs: &[[Value; 3]], ys: &[Value]) -> Value {
+    xs.iter().zip(ys).map(|(x, y)| (&n.forward(x)[0] - y).powi(2)).sum()
+}
+
+fn main() {
+    let n: MLP<3, 4, 4, 1> = MLP::new(Activations::Tanh, Activations::Tanh, Activations::Linear);
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        let loss = loss(&n, &XS.map(|x| x.map(Value::from)), &YS.map(Value::from));
+        n.parameters().for_each(|p| p.zero_grad());
+        loss.backward();
+        n.parameters().for_each(|p| p.adjust(-0.01));
+    }
+    let rebuild = start.elapsed() / STEPS;
+
+    // Inputs and targets become slots, fed in the order they were traced with
+    let (xs, ys) = (XS.map(|x| x.map(Value::from)), YS.map(Value::from));
+    let slots: Vec<&Value> = xs.iter().flatten().chain(&ys).collect();
+    let mut trace = Trace::new(&loss(&n, &xs, &ys), &slots, n.parameters());
+    let data: Vec<f32> = XS.iter().flatten().chain(&YS).copied().collect();
+
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        trace.forward(&data);
+        n.parameters().for_each(|p| p.zero_grad());
+        trace.backward();
+        n.parameters().for_each(|p| p.adjust(-0.01));
+    }
+    let replay = start.elapsed() / STEPS;
+
+    let mut trace = Trace::new(&loss(&n, &xs, &ys), &slots, n.parameters());
+    let report = trace.optimize();
+    let start = Instant::now();
+    for _ in 0..STEPS {
+        trace.forward(&data);
+        n.parameters().for_each(|p| p.zero_grad());
+        trace.backward();
+        n.parameters().for_each(|p| p.adjust(-0.01));
+    }
+    let optimized = start.elapsed() / STEPS;
+
+    println!("Rebuilding the graph: {rebuild:?} per step");
+    println!("Replaying the trace:  {replay:?} per step");
+    println!("Optimized trace:      {optimized:?} per step, {report}");
+    println!("Speedup: {:.1}x", rebuild.as_secs_f64() / replay.as_secs_f64());
+}
@@ -16,11 +16,11 @@ fn main() {
     let adjust = -0.01;
     let mut pb = tqdm!(total = range);
     let _ = pb.refresh();
-    let ys = vec![1.0, -1.0, -1.0, 1.0]; // desired targets
+    let ys = [1.0, -1.0, -1.0, 1.0]; // desired targets
 
     let n: MLP<3, 4, 4, 1> = MLP::new(Activations::Relu, Activations::Relu, Activations::Linear);
 
-    let xs = vec![vec![2.0, 3.0, -1.0], vec![3.0, -1.0, 0.5], vec![0.5, 1.0, 1.0], vec![1.0, 1.0, -1.0]];
+    let xs = [[2.0, 3.0, -1.0], [3.0, -1.0, 0.5], [0.5, 1.0, 1.0], [1.0, 1.0, -1.0]];
 
     for k in 0..range {
         // Forward pass
@@ -35,7 +35,7 @@ fn main() {
             .clone()
             .into_iter()
             .zip(ys.iter().map(|y| Value::from(*y)))
-            .map(|(yout, ygt)| (yout - ygt).pow(&2.0.into()))
+            .map(|(yout, ygt)| (yout - ygt).powi(2))
             .sum();
 
         // Backward pass
@@ -1,10 +1,20 @@
 use proc_macro::TokenStream;
+use proc_macro2::TokenTree;
 use quote::quote;
-use syn::{LitInt, parse_macro_input};
+use syn::LitInt;
 
 #[proc_macro]
 pub fn generate_mlp(input: TokenStream) -> TokenStream {
-    let n_lit = parse_macro_input!(input as LitInt);
+    // Input is either `N` or `$crate, N`, the crate path is needed to name micrograd::engine::Float
+    let tokens: Vec<TokenTree> = proc_macro2::TokenStream::from(input).into_iter().collect();
+    let (krate, n_tokens) = match tokens.iter().rposition(|t| matches!(t, TokenTree::Punct(p) if p.as_char() == ',')) {
+        Some(i) => (tokens[..i].iter().cloned().collect(), &tokens[i + 1..]),
+        None => (quote! { ::micrograd }, &tokens[..]),
+    };
+    let n_lit: LitInt = match syn::parse2(n_tokens.iter().cloned().collect()) {
+        Ok(n) => n,
+        Err(e) => return e.to_compile_error().into(),
+    };
     let num_sizes: usize = match n_lit.base10_parse() {
         Ok(n) => n,
         Err(e) => return syn::Error::new(n_lit.span(), e).to_compile_error().into(),
@@ -19,6 +29,7 @@ pub fn generate_mlp(input: TokenStream) -> TokenStream {
     let span = proc_macro2::Span::call_site();
     let gens: Vec<syn::Ident> = (1..=num_sizes).map(|i| syn::Ident::new(&format!("N{}", i), span)).collect();
     let gens_with_const: Vec<_> = gens.iter().map(|g| quote! { const #g: usize }).collect();
+    let float = quote! { #krate::engine::Float };
 
     let num_layers = num_sizes - 1;
     let layer_names: Vec<syn::Ident> = (1..=num_layers).map(|i| syn::Ident::new(&format!("l{}", i), span)).collect();
@@ -27,7 +38,7 @@ pub fn generate_mlp(input: TokenStream) -> TokenStream {
         let li = &layer_names[i];
         let inn = &gens[i];
         let out = &gens[i + 1];
-        quote! { #li: Layer::<#inn, #out> }
+        quote! { #li: Layer::<#inn, #out, T> }
     });
 
     let act_params: Vec<syn::Ident> = (1..=num_layers).map(|i| syn::Ident::new(&format!("act{}", i), span)).collect();
@@ -38,13 +49,17 @@ pub fn generate_mlp(input: TokenStream) -> TokenStream {
         let inn = &gens[i];
         let out = &gens[i + 1];
         let act = &act_params[i];
-        quote! { #li: Layer::<#inn, #out>::new(#act) }
+        quote! { #li: Layer::<#inn, #out, T>::new(#act) }
     });
 
     let forward_expr = layer_names.iter().fold(quote! { x }, |acc, layer| {
         quote! { self.#layer.forward(& #acc) }
     });
 
+    let forward_dual_expr = layer_names.iter().fold(quote! { x }, |acc, layer| {
+        quote! { self.#layer.forward_dual(& #acc) }
+    });
+
     let params_expr = {
         let first_layer = &layer_names[0];
         layer_names[1..].iter().fold(quote! { self.#first_layer.parameters() }, |acc, layer| {
@@ -61,7 +76,7 @@ pub fn generate_mlp(input: TokenStream) -> TokenStream {
     });
 
     let debug_impl = quote! {
-        impl< #( #gens_with_const ),* > std::fmt::Debug for MLP< #( #gens ),* > {
+        impl< #( #gens_with_const, )* T: #float > std::fmt::Debug for MLP< #( #gens, )* T > {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 write!(f, "MLP of [")?;
                 #first_write
@@ -73,20 +88,30 @@ pub fn generate_mlp(input: TokenStream) -> TokenStream {
     };
 
     quote! {
-        pub struct MLP< #( #gens_with_const ),* > {
+        pub struct MLP< #( #gens_with_const, )* T: #float = f32 > {
             #( #layer_fields ),*
         }
 
-        impl< #( #gens_with_const ),* > MLP< #( #gens ),* > {
+        impl< #( #gens_with_const, )* T: #float > MLP< #( #gens, )* T > {
             pub fn new( #( #act_params_with_type ),* ) -> Self {
                 Self { #( #layer_inits ),* }
             }
 
-            pub fn forward(&self, x: &[Value; N1]) -> [Value; #last_gen] {
+            pub fn forward(&self, x: &[Value<T>; N1]) -> [Value<T>; #last_gen] {
                 #forward_expr
             }
 
-            pub fn parameters(&self) -> impl Iterator<Item = &Value> {
+            // Checks the length of x instead of taking an array, for inputs that come in as slices
+            pub fn try_forward(&self, x: &[Value<T>]) -> ::std::result::Result<[Value<T>; #last_gen], #krate::Error> {
+                let x: &[Value<T>; N1] = x.try_into().map_err(|_| #krate::Error::InputSize { expected: N1, found: x.len() })?;
+                Ok(self.forward(x))
+            }
+
+            pub fn forward_dual(&self, x: &[#krate::dual::Dual<T>; N1]) -> [#krate::dual::Dual<T>; #last_gen] {
+                #forward_dual_expr
+            }
+
+            pub fn parameters(&self) -> impl Iterator<Item = &Value<T>> {
                 #params_expr
             }
         }
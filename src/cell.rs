@@ -0,0 +1,89 @@
+/*
+----------------------------------------------------------------------------------
+Storage used by Value. By default a graph lives on one thread (Rc + RefCell), with
+the `sync` feature it switches to Arc + Mutex so graphs can cross threads and
+several threads can accumulate gradients into the same parameters. What few nodes
+need beyond that is set once in a OnceCell (OnceLock with `sync`), and the hooks on
+it sit in a RefCell (RwLock with `sync`).
+----------------------------------------------------------------------------------
+*/
+#[cfg(not(feature = "sync"))]
+pub use std::{
+    cell::{OnceCell, Ref, RefCell, RefCell as Cell},
+    rc::Rc as Ptr,
+};
+
+#[cfg(feature = "sync")]
+pub use self::sync::{Cell, Ref, RefCell};
+#[cfg(feature = "sync")]
+pub use std::sync::{Arc as Ptr, OnceLock as OnceCell};
+
+#[cfg(feature = "sync")]
+mod sync {
+    use std::{
+        fmt::{Debug, Display, Formatter, Result},
+        ops::Deref,
+        sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    };
+
+    // Same borrow/borrow_mut surface as RefCell. Reads copy the value out instead of holding the lock,
+    // so `a * a` never locks the same cell twice, writes hold the lock for the whole `+=`.
+    pub struct Cell<T>(Mutex<T>);
+
+    pub struct Ref<T>(T);
+
+    // For values that can't be copied out, many readers or one writer
+    pub struct RefCell<T>(RwLock<T>);
+
+    impl<T: Copy> Cell<T> {
+        pub fn new(t: T) -> Self {
+            Cell(Mutex::new(t))
+        }
+
+        pub fn borrow(&self) -> Ref<T> {
+            Ref(*self.borrow_mut())
+        }
+
+        pub fn borrow_mut(&self) -> MutexGuard<'_, T> {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T> RefCell<T> {
+        pub fn new(t: T) -> Self {
+            RefCell(RwLock::new(t))
+        }
+
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.0.read().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn borrow_mut(&self) -> RwLockWriteGuard<'_, T> {
+            self.0.write().unwrap_or_else(PoisonError::into_inner)
+        }
+
+        pub fn get_mut(&mut self) -> &mut T {
+            self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+
+    impl<T> Deref for Ref<T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    impl<T: Display> Display for Ref<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl<T: Debug> Debug for Ref<T> {
+        fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+            self.0.fmt(f)
+        }
+    }
+}
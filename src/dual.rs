@@ -0,0 +1,259 @@
+/*
+----------------------------------------------------------------------------------
+Forward mode. A Dual carries a value and its derivative along one direction, every
+op updates both at once, so a single forward pass gives a Jacobian-vector product
+without building a graph. Cheaper than backward when there are few inputs and many
+outputs.
+----------------------------------------------------------------------------------
+*/
+use crate::engine::{gelu, gelu_grad, sigmoid, Activations, Float};
+use std::{
+    array::from_fn,
+    fmt::{Debug, Formatter, Result},
+    iter::Sum,
+    ops,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Dual<T: Float = f32> {
+    pub data: T,
+    pub tangent: T,
+}
+
+impl<T: Float> Dual<T> {
+    pub fn new(data: T, tangent: T) -> Self {
+        Dual { data, tangent }
+    }
+
+    // The input being differentiated along, tangent 1
+    pub fn var(data: T) -> Self {
+        Dual::new(data, T::one())
+    }
+
+    pub fn constant(data: T) -> Self {
+        Dual::new(data, T::zero())
+    }
+
+    // Chain rule for a scalar function with value y and derivative dy at self
+    fn chain(&self, y: T, dy: T) -> Self {
+        Dual::new(y, dy * self.tangent)
+    }
+
+    // x^y is only differentiable in y for x > 0. Like Value::pow masks it for a leaf exponent, the term is
+    // dropped for an exponent that is constant in this direction, any other gets the real one, NaN included.
+    pub fn pow(&self, b: &Dual<T>) -> Dual<T> {
+        let (x, y) = (self.data, b.data);
+        let out = x.powf(y);
+        let db = if x > T::zero() || b.tangent != T::zero() { out * x.ln() * b.tangent } else { T::zero() };
+        Dual::new(out, y * x.powf(y - T::one()) * self.tangent + db)
+    }
+
+    pub fn powi(&self, n: i32) -> Dual<T> {
+        self.chain(self.data.powi(n), T::from(n).unwrap() * self.data.powi(n - 1))
+    }
+
+    pub fn powf(&self, n: T) -> Dual<T> {
+        self.chain(self.data.powf(n), n * self.data.powf(n - T::one()))
+    }
+
+    pub fn powneg(&self) -> Dual<T> {
+        self.chain(self.data.recip(), -self.data.powi(2).recip())
+    }
+
+    pub fn exp(&self) -> Dual<T> {
+        let y = self.data.exp();
+        self.chain(y, y)
+    }
+
+    pub fn ln(&self) -> Dual<T> {
+        self.chain(self.data.ln(), self.data.recip())
+    }
+
+    pub fn tanh(&self) -> Dual<T> {
+        let y = self.data.tanh();
+        self.chain(y, T::one() - y * y)
+    }
+
+    pub fn relu(&self) -> Dual<T> {
+        let y = self.data.max(T::zero());
+        self.chain(y, if y > T::zero() { T::one() } else { T::zero() })
+    }
+
+    pub fn sigmoid(&self) -> Dual<T> {
+        let y = sigmoid(self.data);
+        self.chain(y, y * (T::one() - y))
+    }
+
+    pub fn leaky_relu(&self, slope: T) -> Dual<T> {
+        let x = self.data;
+        if x > T::zero() {
+            *self
+        } else {
+            self.chain(slope * x, slope)
+        }
+    }
+
+    pub fn elu(&self, alpha: T) -> Dual<T> {
+        let x = self.data;
+        if x > T::zero() {
+            *self
+        } else {
+            self.chain(alpha * x.exp_m1(), alpha * x.exp())
+        }
+    }
+
+    pub fn gelu(&self) -> Dual<T> {
+        self.chain(gelu(self.data), gelu_grad(self.data))
+    }
+
+    pub fn silu(&self) -> Dual<T> {
+        let (x, s) = (self.data, sigmoid(self.data));
+        self.chain(x * s, s + x * s * (T::one() - s))
+    }
+
+    pub fn softplus(&self) -> Dual<T> {
+        let x = self.data;
+        self.chain(x.max(T::zero()) + (-x.abs()).exp().ln_1p(), sigmoid(x))
+    }
+
+    pub fn sqrt(&self) -> Dual<T> {
+        let y = self.data.sqrt();
+        self.chain(y, (y + y).recip())
+    }
+
+    pub fn abs(&self) -> Dual<T> {
+        let x = self.data;
+        let sign = if x > T::zero() {
+            T::one()
+        } else if x < T::zero() {
+            -T::one()
+        } else {
+            T::zero()
+        };
+        self.chain(x.abs(), sign)
+    }
+
+    pub fn sin(&self) -> Dual<T> {
+        self.chain(self.data.sin(), self.data.cos())
+    }
+
+    pub fn cos(&self) -> Dual<T> {
+        self.chain(self.data.cos(), -self.data.sin())
+    }
+
+    pub fn tan(&self) -> Dual<T> {
+        let y = self.data.tan();
+        self.chain(y, T::one() + y * y)
+    }
+
+    pub fn log1p(&self) -> Dual<T> {
+        self.chain(self.data.ln_1p(), (T::one() + self.data).recip())
+    }
+
+    pub fn expm1(&self) -> Dual<T> {
+        let y = self.data.exp_m1();
+        self.chain(y, y + T::one())
+    }
+
+    // Ties take the average of both tangents, the forward mode version of the split in Value::max
+    pub fn max(a: &Dual<T>, b: &Dual<T>) -> Dual<T> {
+        if a.data > b.data || b.data.is_nan() {
+            *a
+        } else if b.data > a.data || a.data.is_nan() {
+            *b
+        } else {
+            Dual::new(a.data, (a.tangent + b.tangent) / T::from(2.0).unwrap())
+        }
+    }
+
+    pub fn min(a: &Dual<T>, b: &Dual<T>) -> Dual<T> {
+        -Dual::max(&-*a, &-*b)
+    }
+
+    pub fn clamp(&self, lo: T, hi: T) -> Dual<T> {
+        if self.data < lo {
+            Dual::constant(lo)
+        } else if self.data > hi {
+            Dual::constant(hi)
+        } else {
+            *self
+        }
+    }
+
+    pub fn activate<const I: usize>(a: [Dual<T>; I], b: &Activations) -> [Dual<T>; I] {
+        match b {
+            Activations::Linear => a,
+            Activations::Tanh => from_fn(|i| a[i].tanh()),
+            Activations::Relu => from_fn(|i| a[i].relu()),
+            Activations::Sigmoid => from_fn(|i| a[i].sigmoid()),
+            Activations::LeakyRelu(slope) => from_fn(|i| a[i].leaky_relu(T::from(*slope).unwrap())),
+            Activations::Elu(alpha) => from_fn(|i| a[i].elu(T::from(*alpha).unwrap())),
+            Activations::Gelu => from_fn(|i| a[i].gelu()),
+            Activations::Silu => from_fn(|i| a[i].silu()),
+            Activations::Softplus => from_fn(|i| a[i].softplus()),
+        }
+    }
+}
+
+impl<T: Float> From<T> for Dual<T> {
+    fn from(t: T) -> Self {
+        Dual::constant(t)
+    }
+}
+
+impl<T: Float> Debug for Dual<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "Dual(data={}, tangent={})", self.data, self.tangent)
+    }
+}
+
+/*
+------------------------------------------------------------------------------------------------
+Same operator overloads as Value, for owned Duals and references
+------------------------------------------------------------------------------------------------
+*/
+macro_rules! impl_ops {
+    ( $( $Trait:ident::$method:ident => |$a:ident, $b:ident| $body:expr ),* $(,)? ) => {
+        $(
+            impl<T: Float> ops::$Trait<Dual<T>> for Dual<T> {
+                type Output = Dual<T>;
+                fn $method(self, other: Dual<T>) -> Self::Output {
+                    let ($a, $b) = (self, other);
+                    $body
+                }
+            }
+            impl<'a, 'b, T: Float> ops::$Trait<&'b Dual<T>> for &'a Dual<T> {
+                type Output = Dual<T>;
+                fn $method(self, other: &'b Dual<T>) -> Self::Output {
+                    let ($a, $b) = (*self, *other);
+                    $body
+                }
+            }
+        )*
+    }
+}
+impl_ops!(
+    Add::add => |a, b| Dual::new(a.data + b.data, a.tangent + b.tangent),
+    Sub::sub => |a, b| Dual::new(a.data - b.data, a.tangent - b.tangent),
+    Mul::mul => |a, b| Dual::new(a.data * b.data, a.tangent * b.data + a.data * b.tangent),
+    Div::div => |a, b| Dual::new(a.data / b.data, (a.tangent * b.data - a.data * b.tangent) / (b.data * b.data)),
+);
+
+impl<T: Float> ops::Neg for Dual<T> {
+    type Output = Dual<T>;
+    fn neg(self) -> Self::Output {
+        Dual::new(-self.data, -self.tangent)
+    }
+}
+impl<T: Float> ops::Neg for &Dual<T> {
+    type Output = Dual<T>;
+    fn neg(self) -> Self::Output {
+        -*self
+    }
+}
+
+impl<T: Float> Sum for Dual<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Dual::constant(T::zero()), |a, b| a + b)
+    }
+}
@@ -1,67 +1,297 @@
+use crate::cell::{Cell, OnceCell, Ptr, RefCell};
+use crate::Error;
 use std::{
     array::from_fn,
-    cell::RefCell,
-    collections::HashSet,
-    fmt::{Debug, Formatter, Result},
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Display, Formatter, Result},
     hash::{Hash, Hasher},
     iter::Sum,
     ops,
-    rc::Rc,
+    thread::LocalKey,
 };
 
+// Scalar type a Value can hold, implemented for f32, f64 and any user type that satisfies the bounds
+pub trait Float: num_traits::Float + ops::AddAssign + Display + Debug + Send + Sync + 'static {}
+impl<T: num_traits::Float + ops::AddAssign + Display + Debug + Send + Sync + 'static> Float for T {}
+
 #[derive(Clone)]
-pub struct Value(Rc<ValueData>);
+pub struct Value<T: Float = f32>(Ptr<ValueData<T>>);
 
-pub struct ValueData {
-    pub data: RefCell<f32>,
-    pub grad: RefCell<f32>,
+pub struct ValueData<T: Float = f32> {
+    pub data: Cell<T>,
+    pub grad: Cell<T>,
     pub op: Option<&'static str>,
-    pub prev: Vec<Value>,
-    pub _backward: Option<fn(value: &Value)>,
+    pub prev: Vec<Value<T>>,
+    pub _backward: Option<fn(value: &Value<T>)>,
+    // Only set for a custom op or once a hook is registered, so other nodes pay for one pointer
+    pub extra: OnceCell<Box<Extra<T>>>,
+}
+
+pub struct Extra<T: Float = f32> {
+    pub custom: Option<Ptr<dyn CustomOp<T>>>,
+    pub hooks: RefCell<Vec<Hook<T>>>,
+}
+
+// Runs on a node's gradient once backward has finished adding into it and returns the gradient to keep.
+// It only has to be Send + Sync with the sync feature, without it a hook can hold an Rc.
+#[cfg(not(feature = "sync"))]
+pub trait HookFn<T>: Fn(T) -> T + 'static {}
+#[cfg(not(feature = "sync"))]
+impl<T, F: Fn(T) -> T + 'static> HookFn<T> for F {}
+
+#[cfg(feature = "sync")]
+pub trait HookFn<T>: Fn(T) -> T + Send + Sync + 'static {}
+#[cfg(feature = "sync")]
+impl<T, F: Fn(T) -> T + Send + Sync + 'static> HookFn<T> for F {}
+
+pub type Hook<T> = Ptr<dyn HookFn<T>>;
+
+// Adds the gradient of a node into the grads of its inputs
+pub(crate) type Backward<T> = fn(&Value<T>);
+
+// A differentiable op defined outside this crate, applied with Value::apply.
+// backward returns the local gradient for every input, already multiplied by grad.
+pub trait CustomOp<T: Float = f32>: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn forward(&self, inputs: &[T]) -> T;
+    fn backward(&self, inputs: &[T], out: T, grad: T) -> Vec<T>;
+}
+
+// Scalar form of a built-in op, found by its op name with scalar_op
+#[derive(Clone, Copy)]
+pub struct ScalarOp<T: Float = f32> {
+    // Number of inputs, the constant of a param op counts as one
+    pub arity: usize,
+    pub forward: fn(&[T]) -> T,
+    // (inputs, out, grad of out, grads) writes the gradient of every input, already multiplied by grad
+    pub backward: fn(&[T], T, T, &mut [T]),
 }
 
 pub enum Activations {
     Linear,
     Relu,
     Tanh,
+    Sigmoid,
+    LeakyRelu(f64),
+    Elu(f64),
+    Gelu,
+    Silu,
+    Softplus,
+}
+
+thread_local! {
+    static GRAD_ENABLED: std::cell::Cell<bool> = const { std::cell::Cell::new(true) };
+    static ANOMALY_ENABLED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+// Sets a thread-local flag for the duration of f, it is put back even if f panics
+fn with_flag<R>(flag: &'static LocalKey<std::cell::Cell<bool>>, value: bool, f: impl FnOnce() -> R) -> R {
+    struct Restore(&'static LocalKey<std::cell::Cell<bool>>, bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            self.0.with(|g| g.set(self.1));
+        }
+    }
+    let _restore = Restore(flag, flag.with(|g| g.replace(value)));
+    f()
+}
+
+// Runs f with graph building switched off on this thread, every op inside returns a leaf
+pub fn no_grad<R>(f: impl FnOnce() -> R) -> R {
+    with_flag(&GRAD_ENABLED, false, f)
+}
+
+// Switches graph building back on for f, for code inside no_grad that needs a graph of its own
+pub fn enable_grad<R>(f: impl FnOnce() -> R) -> R {
+    with_flag(&GRAD_ENABLED, true, f)
+}
+
+// Runs f with every op and backward step checked for NaN and inf on this thread. The first one
+// panics with the op that produced it, its inputs and, in backward, the path from the root.
+pub fn detect_anomaly<R>(f: impl FnOnce() -> R) -> R {
+    with_flag(&ANOMALY_ENABLED, true, f)
 }
 
-impl ValueData {
-    fn new(data: f32, op: Option<&'static str>, prev: Vec<Value>, _backward: Option<fn(value: &Value)>) -> ValueData {
+pub fn is_anomaly_enabled() -> bool {
+    ANOMALY_ENABLED.with(|g| g.get())
+}
+
+pub fn is_grad_enabled() -> bool {
+    GRAD_ENABLED.with(|g| g.get())
+}
+
+impl<T: Float> ValueData<T> {
+    fn new(data: T, op: Option<&'static str>, prev: Vec<Value<T>>, _backward: Option<fn(value: &Value<T>)>) -> ValueData<T> {
         ValueData {
-            data: RefCell::new(data),
-            grad: RefCell::new(0.0),
+            data: Cell::new(data),
+            grad: Cell::new(T::zero()),
             op,
             prev,
             _backward,
+            extra: OnceCell::new(),
         }
     }
+
+    fn with_custom(mut self, custom: Option<Ptr<dyn CustomOp<T>>>) -> ValueData<T> {
+        if custom.is_some() {
+            self.extra = OnceCell::from(Box::new(Extra {
+                custom,
+                hooks: RefCell::new(Vec::new()),
+            }));
+        }
+        self
+    }
+
+    pub fn custom(&self) -> Option<&Ptr<dyn CustomOp<T>>> {
+        self.extra.get()?.custom.as_ref()
+    }
 }
 
+// Every op is a scalar forward, a scalar backward giving the local gradients already multiplied by g,
+// and a differentiable backward that builds those same gradients out of Values for grad_graph.
+// The scalar pair drives Value and is also what replays a graph without Values, see scalar_op.
 macro_rules! define_ops {
     (
-        $(binary $bname:ident, $bsym:expr => |$a:ident, $b:ident| $bfwd:expr, |$out:ident| $bbwd:block;)*
-        $(unary $uname:ident, $usym:expr => |$x:ident| $ufwd:expr, |$outu:ident| $ubwd:block;)*
+        $(binary $bname:ident, $bsym:literal => |$a:ident, $b:ident| $bfwd:expr,
+            |$ba:ident, $bb:ident, $bout:ident, $bg:ident| $bbwd:expr,
+            |$ga:ident, $gb:ident, $gout:ident, $gg:ident| $bgrad:expr;)*
+        $(unary $uname:ident, $usym:literal => |$x:ident| $ufwd:expr,
+            |$ux:ident, $uout:ident, $ug:ident| $ubwd:expr,
+            |$gx:ident, $goutu:ident, $ggu:ident| $ugrad:expr;)*
+        $(param $pname:ident($p:ident: $pty:ty), $psym:literal => |$px:ident, $pp:ident| $pfwd:expr,
+            |$pbx:ident, $pbp:ident, $pout:ident, $pg:ident| $pbwd:expr,
+            |$gpx:ident, $gp:ident, $goutp:ident, $ggp:ident| $pgrad:expr;)*
     ) => {
+        impl<T: Float> Value<T> {
+            fn _grad_graph_op(&self, g: &Value<T>) -> Option<Vec<Value<T>>> {
+                if self.custom().is_some() {
+                    return None;
+                }
+                let prev = &self.prev;
+                match self.op? {
+                    $($bsym => {
+                        let ($ga, $gb, $gout, $gg) = (&prev[0], &prev[1], self, g);
+                        Some($bgrad)
+                    })*
+                    $($usym => {
+                        let ($gx, $goutu, $ggu) = (&prev[0], self, g);
+                        Some($ugrad)
+                    })*
+                    $($psym => {
+                        let ($gpx, $gp, $goutp, $ggp) = (&prev[0], &prev[1], self, g);
+                        Some($pgrad)
+                    })*
+                    _ => None,
+                }
+            }
+        }
+
+        fn builtin_op<T: Float>(op: &str) -> Option<ScalarOp<T>> {
+            match op {
+                $($bsym => Some(ScalarOp {
+                    arity: 2,
+                    forward: |x| {
+                        let ($a, $b) = (x[0], x[1]);
+                        $bfwd
+                    },
+                    backward: |x, out, g, grads| {
+                        let ($ba, $bb, $bout, $bg) = (x[0], x[1], out, g);
+                        (grads[0], grads[1]) = $bbwd;
+                    },
+                }),)*
+                $($usym => Some(ScalarOp {
+                    arity: 1,
+                    forward: |x| {
+                        let $x = x[0];
+                        $ufwd
+                    },
+                    backward: |x, out, g, grads| {
+                        let ($ux, $uout, $ug) = (x[0], out, g);
+                        grads[0] = $ubwd;
+                    },
+                }),)*
+                $($psym => Some(ScalarOp {
+                    arity: 2,
+                    forward: |x| {
+                        let ($px, $pp) = (x[0], x[1]);
+                        $pfwd
+                    },
+                    backward: |x, out, g, grads| {
+                        let ($pbx, $pbp, $pout, $pg) = (x[0], x[1], out, g);
+                        (grads[0], grads[1]) = ($pbwd, T::zero());
+                    },
+                }),)*
+                _ => None,
+            }
+        }
+
+        // The same backward functions the constructors below store in _backward
+        fn builtin_backward<T: Float>(op: &str) -> Option<(&'static str, Backward<T>)> {
+            match op {
+                $($bsym => Some(($bsym, |out| {
+                    let prev = &out.prev;
+                    let ($ba, $bb, $bout, $bg) = (prev[0].data(), prev[1].data(), out.data(), out.grad());
+                    let (da, db) = $bbwd;
+                    *prev[0].grad.borrow_mut() += da;
+                    *prev[1].grad.borrow_mut() += db;
+                })),)*
+                $($usym => Some(($usym, |out| {
+                    let prev = &out.prev;
+                    let ($ux, $uout, $ug) = (prev[0].data(), out.data(), out.grad());
+                    *prev[0].grad.borrow_mut() += $ubwd;
+                })),)*
+                $($psym => Some(($psym, |out| {
+                    let prev = &out.prev;
+                    let ($pbx, $pbp, $pout, $pg) = (prev[0].data(), prev[1].data(), out.data(), out.grad());
+                    *prev[0].grad.borrow_mut() += $pbwd;
+                })),)*
+                _ => None,
+            }
+        }
+
         $(
-            impl Value {
-                pub fn $bname($a: &Value, $b: &Value) -> Value {
-                    let _backward: fn(&Value) = |$out| $bbwd;
+            impl<T: Float> Value<T> {
+                pub fn $bname(a: &Value<T>, b: &Value<T>) -> Value<T> {
+                    let _backward: fn(&Value<T>) = |out| {
+                        let prev = &out.prev;
+                        let ($ba, $bb, $bout, $bg) = (prev[0].data(), prev[1].data(), out.data(), out.grad());
+                        let (da, db) = $bbwd;
+                        *prev[0].grad.borrow_mut() += da;
+                        *prev[1].grad.borrow_mut() += db;
+                    };
+                    let data = {
+                        let ($a, $b) = (a.data(), b.data());
+                        $bfwd
+                    };
+                    if !is_grad_enabled() {
+                        return Value::_no_grad($bsym, data, || vec![a.clone(), b.clone()]);
+                    }
                     Value::new(ValueData::new(
-                        $bfwd,
+                        data,
                         Some($bsym),
-                        vec![$a.clone(), $b.clone()],
+                        vec![a.clone(), b.clone()],
                         Some(_backward),
                     ))
                 }
             }
         )*
         $(
-            impl Value {
-                pub fn $uname(&self) -> Value {
-                    let _backward: fn(&Value) = |$outu| $ubwd;
+            impl<T: Float> Value<T> {
+                pub fn $uname(&self) -> Value<T> {
+                    let _backward: fn(&Value<T>) = |out| {
+                        let prev = &out.prev;
+                        let ($ux, $uout, $ug) = (prev[0].data(), out.data(), out.grad());
+                        *prev[0].grad.borrow_mut() += $ubwd;
+                    };
+                    let data = {
+                        let $x = self.data();
+                        $ufwd
+                    };
+                    if !is_grad_enabled() {
+                        return Value::_no_grad($usym, data, || vec![self.clone()]);
+                    }
                     Value::new(ValueData::new(
-                        { let $x = self; $ufwd },
+                        data,
                         Some($usym),
                         vec![self.clone()],
                         Some(_backward),
@@ -69,111 +299,444 @@ macro_rules! define_ops {
                 }
             }
         )*
+        $(
+            // The constant is kept as a second input so backward can read it, it never receives a gradient
+            impl<T: Float> Value<T> {
+                pub fn $pname(&self, $p: $pty) -> Value<T> {
+                    let _backward: fn(&Value<T>) = |out| {
+                        let prev = &out.prev;
+                        let ($pbx, $pbp, $pout, $pg) = (prev[0].data(), prev[1].data(), out.data(), out.grad());
+                        *prev[0].grad.borrow_mut() += $pbwd;
+                    };
+                    let $p = T::from($p).unwrap();
+                    let data = {
+                        let ($px, $pp) = (self.data(), $p);
+                        $pfwd
+                    };
+                    if !is_grad_enabled() {
+                        return Value::_no_grad($psym, data, || vec![self.clone(), Value::from($p)]);
+                    }
+                    Value::new(ValueData::new(
+                        data,
+                        Some($psym),
+                        vec![self.clone(), Value::from($p)],
+                        Some(_backward),
+                    ))
+                }
+            }
+        )*
     };
 }
 
 define_ops! {
-    binary add, "+" => |a,b| *a.0.data.borrow() + *b.0.data.borrow(), |out| {
-        *out.0.prev[0].0.grad.borrow_mut() += *out.0.grad.borrow();
-        *out.0.prev[1].0.grad.borrow_mut() += *out.0.grad.borrow();
-    };
-    binary mul, "*" => |a,b| *a.0.data.borrow() * *b.0.data.borrow(), |out| {
-        let a_data = *out.0.prev[0].0.data.borrow();
-        let b_data = *out.0.prev[1].0.data.borrow();
-        *out.0.prev[0].0.grad.borrow_mut() += b_data * *out.0.grad.borrow();
-        *out.0.prev[1].0.grad.borrow_mut() += a_data * *out.0.grad.borrow();
+    binary add, "+" => |a, b| a + b, |_a, _b, _out, g| (g, g),
+        |_a, _b, _out, g| vec![g.clone(), g.clone()];
+    binary mul, "*" => |a, b| a * b, |a, b, _out, g| (b * g, a * g),
+        |a, b, _out, g| vec![g * b, g * a];
+    // d/db is only defined for a positive base. pow uses pow_op for a leaf exponent, usually a constant like the 2
+    // of (x - y).pow(2), and masks d/db so it doesn't turn into NaN. An exponent computed in the graph goes
+    // through pow_var and gets the real d/db, NaN included, so detect_anomaly can catch it.
+    binary pow_op, "^" => |a, b| a.powf(b), |a, b, out, g| (
+        b * a.powf(b - T::one()) * g,
+        if a > T::zero() { out * a.ln() * g } else { T::zero() },
+    ), |a, b, out, g| vec![
+        &(g * b) * &Value::pow_op(a, &(b - &lit(1.0))),
+        if a.data() > T::zero() { &(g * out) * &a.ln() } else { lit(0.0) },
+    ];
+    binary pow_var, "^v" => |a, b| a.powf(b), |a, b, out, g| (b * a.powf(b - T::one()) * g, out * a.ln() * g),
+        |a, b, out, g| vec![&(g * b) * &Value::pow_var(a, &(b - &lit(1.0))), &(g * out) * &a.ln()];
+    // Ties split the gradient evenly between both inputs, min is max of the negated inputs
+    binary max, "max" => |a, b| a.max(b), |a, b, _out, g| tie_split(a, b, g), |a, b, _out, g| {
+        let (wa, wb) = tie_split(a.data(), b.data(), T::one());
+        vec![g * &Value::from(wa), g * &Value::from(wb)]
     };
-    binary pow_op, "^" => |a,b| a.0.data.borrow().powf(*b.0.data.borrow()), |out| {
-        let base = *out.0.prev[0].0.data.borrow();
-        let exp  = *out.0.prev[1].0.data.borrow();
-        let gout = *out.0.grad.borrow();
-        let y    = *out.0.data.borrow(); 
-        *out.0.prev[0].0.grad.borrow_mut() += exp * base.powf(exp - 1.0) * gout;
-        *out.0.prev[1].0.grad.borrow_mut() += y * base.ln() * gout;
+    binary min, "min" => |a, b| a.min(b), |a, b, _out, g| tie_split(-a, -b, g), |a, b, _out, g| {
+        let (wa, wb) = tie_split(-a.data(), -b.data(), T::one());
+        vec![g * &Value::from(wa), g * &Value::from(wb)]
     };
-    unary powneg, "^-" => |x| 1.0 / *x.0.data.borrow(), |out| {
-        let base = &out.0.prev[0];
-        let mut grad = base.0.grad.borrow_mut();
-        *grad += -(1.0 / (*base.0.data.borrow()).powf(2.0)) * *out.0.grad.borrow();
+    unary powneg, "^-" => |x| x.recip(), |x, _out, g| -x.powi(2).recip() * g,
+        |x, _out, g| vec![-&(g * &x.powi(-2))];
+    unary exp, "exp" => |x| x.exp(), |_x, out, g| out * g,
+        |_x, out, g| vec![g * out];
+    unary ln, "log" => |x| x.ln(), |x, _out, g| g / x,
+        |x, _out, g| vec![g / x];
+    unary tanh, "tanh" => |x| x.tanh(), |_x, out, g| (T::one() - out * out) * g,
+        |_x, out, g| vec![g * &(&lit(1.0) - &out.powi(2))];
+    unary relu, "ReLU" => |x| x.max(T::zero()), |_x, out, g| if out > T::zero() { g } else { T::zero() },
+        |x, _out, g| vec![g * &lit(if x.data() > T::zero() { 1.0 } else { 0.0 })];
+    unary sigmoid, "sigmoid" => |x| sigmoid(x), |_x, out, g| out * (T::one() - out) * g,
+        |_x, out, g| vec![&(g * out) * &(&lit(1.0) - out)];
+    // tanh approximation, 0.5x(1 + tanh(sqrt(2/pi)(x + 0.044715x^3)))
+    unary gelu, "GELU" => |x| gelu(x), |x, _out, g| gelu_grad(x) * g, |x, _out, g| {
+        let (c, k) = (lit(GELU_C), lit(GELU_K));
+        let t = (&c * &(x + &(&k * &x.powi(3)))).tanh();
+        let inner = &(&lit(1.0) - &t.powi(2)) * &(&c * &(&lit(1.0) + &(&lit(3.0) * &(&k * &x.powi(2)))));
+        vec![g * &(&lit(0.5) * &(&(&lit(1.0) + &t) + &(x * &inner)))]
     };
-    unary exp, "exp" => |x| x.0.data.borrow().exp(), |out| {
-        *out.0.prev[0].0.grad.borrow_mut() += *out.0.data.borrow() * *out.0.grad.borrow();
-    };
-    unary ln, "log" => |x| x.0.data.borrow().ln(), |out| {
-        let xv = *out.0.prev[0].0.data.borrow();
-        *out.0.prev[0].0.grad.borrow_mut() += *out.0.grad.borrow() / xv;
-    };
-    unary tanh, "tanh" => |x| x.0.data.borrow().tanh(), |out| {
-        let y = out.0.prev[0].0.data.borrow().tanh();
-        *out.0.prev[0].0.grad.borrow_mut() += (1.0 - y*y) * *out.0.grad.borrow();
-    };
-    unary relu, "ReLU" => |x| x.0.data.borrow().max(0.0), |out| {
-        let g = if *out.0.data.borrow() > 0.0 { *out.0.grad.borrow() } else { 0.0 };
-        *out.0.prev[0].0.grad.borrow_mut() += g;
+    unary silu, "SiLU" => |x| x * sigmoid(x), |x, _out, g| {
+        let s = sigmoid(x);
+        (s + x * s * (T::one() - s)) * g
+    }, |x, _out, g| {
+        let s = x.sigmoid();
+        vec![g * &(&s + &(&(x * &s) * &(&lit(1.0) - &s)))]
     };
+    // ln(1 + e^x) written so large x doesn't overflow
+    unary softplus, "softplus" => |x| x.max(T::zero()) + (-x.abs()).exp().ln_1p(), |x, _out, g| sigmoid(x) * g,
+        |x, _out, g| vec![g * &x.sigmoid()];
+    unary sqrt, "sqrt" => |x| x.sqrt(), |_x, out, g| g / (out + out),
+        |_x, out, g| vec![g / &(out + out)];
+    // Subgradient 0 at x = 0
+    unary abs, "abs" => |x| x.abs(), |x, _out, g| sign(x) * g,
+        |x, _out, g| vec![g * &Value::from(sign(x.data()))];
+    unary sin, "sin" => |x| x.sin(), |x, _out, g| x.cos() * g,
+        |x, _out, g| vec![g * &x.cos()];
+    unary cos, "cos" => |x| x.cos(), |x, _out, g| -x.sin() * g,
+        |x, _out, g| vec![-&(g * &x.sin())];
+    unary tan, "tan" => |x| x.tan(), |_x, out, g| (T::one() + out * out) * g,
+        |_x, out, g| vec![g * &(&lit(1.0) + &out.powi(2))];
+    unary log1p, "log1p" => |x| x.ln_1p(), |x, _out, g| g / (T::one() + x),
+        |x, _out, g| vec![g / &(&lit(1.0) + x)];
+    unary expm1, "expm1" => |x| x.exp_m1(), |_x, out, g| (out + T::one()) * g,
+        |_x, out, g| vec![g * &(out + &lit(1.0))];
+    param powi(n: i32), "^i" => |x, n| x.powi(n.to_i32().unwrap()), |x, n, _out, g| n * x.powi(n.to_i32().unwrap() - 1) * g,
+        |x, n, _out, g| vec![&(g * n) * &x.powi(n.data().to_i32().unwrap() - 1)];
+    param powf(n: T), "^f" => |x, n| x.powf(n), |x, n, _out, g| n * x.powf(n - T::one()) * g,
+        |x, n, _out, g| vec![&(g * n) * &x.powf(n.data() - T::one())];
+    param leaky_relu(slope: T), "LeakyReLU" => |x, slope| if x > T::zero() { x } else { slope * x },
+        |x, slope, _out, g| (if x > T::zero() { T::one() } else { slope }) * g,
+        |x, slope, _out, g| vec![if x.data() > T::zero() { g.clone() } else { g * slope }];
+    param elu(alpha: T), "ELU" => |x, alpha| if x > T::zero() { x } else { alpha * x.exp_m1() },
+        |x, alpha, _out, g| (if x > T::zero() { T::one() } else { alpha * x.exp() }) * g,
+        |x, alpha, out, g| vec![if x.data() > T::zero() { g.clone() } else { g * &(out + alpha) }];
+}
+
+// sqrt(2/pi)
+const GELU_C: f64 = 0.7978845608028654;
+const GELU_K: f64 = 0.044715;
+
+// Constant leaf, used by the differentiable backward closures
+fn lit<T: Float>(v: f64) -> Value<T> {
+    Value::from(T::from(v).unwrap())
+}
+
+// Gradient for max(a, b), all of it goes to the larger input and half to each on a tie. max
+// passes over a NaN and gives the other input, so that input gets all of it too.
+fn tie_split<T: Float>(a: T, b: T, g: T) -> (T, T) {
+    if a > b || b.is_nan() {
+        (g, T::zero())
+    } else if a < b || a.is_nan() {
+        (T::zero(), g)
+    } else {
+        let half = g / (T::one() + T::one());
+        (half, half)
+    }
+}
+
+// NaN stays NaN, max would turn it into lo
+fn clamp<T: Float>(x: T, lo: T, hi: T) -> T {
+    if x.is_nan() {
+        x
+    } else {
+        x.max(lo).min(hi)
+    }
+}
+
+fn sign<T: Float>(x: T) -> T {
+    if x > T::zero() {
+        T::one()
+    } else if x < T::zero() {
+        -T::one()
+    } else {
+        T::zero()
+    }
+}
+
+// Everything define_ops knows plus clamp and select. Custom ops are not in here, they carry their own CustomOp.
+pub fn scalar_op<T: Float>(op: &str) -> Option<ScalarOp<T>> {
+    builtin_op(op).or(match op {
+        "clamp" => Some(ScalarOp {
+            arity: 3,
+            forward: |x| clamp(x[0], x[1], x[2]),
+            backward: |x, _out, g, grads| {
+                grads[0] = if x[1] <= x[0] && x[0] <= x[2] { g } else { T::zero() };
+                (grads[1], grads[2]) = (T::zero(), T::zero());
+            },
+        }),
+        "select" => Some(ScalarOp {
+            arity: 3,
+            forward: |x| if x[0] > T::zero() { x[1] } else { x[2] },
+            backward: |x, _out, g, grads| {
+                let (a, b) = if x[0] > T::zero() { (g, T::zero()) } else { (T::zero(), g) };
+                (grads[0], grads[1], grads[2]) = (T::zero(), a, b);
+            },
+        }),
+        _ => None,
+    })
 }
 
-impl Value {
-    pub fn from<T: Into<Value>>(t: T) -> Self {
+// Op name and backward function of everything define_ops knows plus clamp and select, so a graph can be rebuilt from
+// op names. Custom ops aren't in here, their backward is always Value::_custom_backward.
+pub(crate) fn backward_fn<T: Float>(op: &str) -> Option<(&'static str, Backward<T>)> {
+    builtin_backward(op).or(match op {
+        "clamp" => Some(("clamp", Value::_clamp_backward)),
+        "select" => Some(("select", Value::_select_backward)),
+        _ => None,
+    })
+}
+
+// Split on the sign so exp never overflows
+pub(crate) fn sigmoid<T: Float>(x: T) -> T {
+    if x >= T::zero() {
+        (T::one() + (-x).exp()).recip()
+    } else {
+        let e = x.exp();
+        e / (T::one() + e)
+    }
+}
+
+pub(crate) fn gelu<T: Float>(x: T) -> T {
+    let (half, c, k) = (T::from(0.5).unwrap(), T::from(GELU_C).unwrap(), T::from(GELU_K).unwrap());
+    half * x * (T::one() + (c * (x + k * x.powi(3))).tanh())
+}
+
+pub(crate) fn gelu_grad<T: Float>(x: T) -> T {
+    let (half, c, k) = (T::from(0.5).unwrap(), T::from(GELU_C).unwrap(), T::from(GELU_K).unwrap());
+    let t = (c * (x + k * x.powi(3))).tanh();
+    half * (T::one() + t) + half * x * (T::one() - t * t) * c * (T::one() + T::from(3.0).unwrap() * k * x * x)
+}
+
+impl<T: Float> Value<T> {
+    pub fn from<U: Into<Value<T>>>(t: U) -> Self {
         t.into()
     }
 
-    fn new(value: ValueData) -> Self {
-        Value(Rc::new(value))
+    fn new(value: ValueData<T>) -> Self {
+        let v = Value(Ptr::new(value));
+        if let Some(op) = v.op {
+            Value::_check_forward(op, v.data(), || v.prev.clone());
+        }
+        v
+    }
+
+    // What an op returns inside no_grad, a leaf that is still checked like a node of the graph would be
+    fn _no_grad(op: &'static str, data: T, inputs: impl FnOnce() -> Vec<Value<T>>) -> Value<T> {
+        Value::_check_forward(op, data, inputs);
+        Value::from(data)
+    }
+
+    // Every op result goes through here, the inputs are only collected when there is an anomaly to report
+    fn _check_forward(op: &str, data: T, inputs: impl FnOnce() -> Vec<Value<T>>) {
+        if is_anomaly_enabled() && !data.is_finite() {
+            panic!("anomaly in forward: {} gave {} from inputs {}", op, data, Value::_describe(&inputs()));
+        }
     }
 
-    pub fn data(&self) -> f32 {
+    pub fn data(&self) -> T {
         *self.0.data.borrow()
     }
 
-    pub fn grad(&self) -> f32 {
+    pub fn grad(&self) -> T {
         *self.0.grad.borrow()
     }
 
     pub fn zero_grad(&self) {
-        *self.0.grad.borrow_mut() = 0.0;
+        *self.0.grad.borrow_mut() = T::zero();
+    }
+
+    // Gradient passes through inside [lo, hi] and is 0 where the value got clamped
+    pub fn clamp(&self, lo: T, hi: T) -> Value<T> {
+        let data = clamp(self.data(), lo, hi);
+        if !is_grad_enabled() {
+            return Value::_no_grad("clamp", data, || vec![self.clone(), Value::from(lo), Value::from(hi)]);
+        }
+        Value::new(ValueData::new(
+            data,
+            Some("clamp"),
+            vec![self.clone(), Value::from(lo), Value::from(hi)],
+            Some(Value::_clamp_backward),
+        ))
+    }
+
+    fn _clamp_backward(out: &Value<T>) {
+        let x = *out.0.prev[0].0.data.borrow();
+        let (lo, hi) = (*out.0.prev[1].0.data.borrow(), *out.0.prev[2].0.data.borrow());
+        if lo <= x && x <= hi {
+            *out.0.prev[0].0.grad.borrow_mut() += *out.0.grad.borrow();
+        }
+    }
+
+    // a where cond > 0 and b everywhere else. The gradient goes to the branch that was picked, cond gets none.
+    pub fn select(cond: &Value<T>, a: &Value<T>, b: &Value<T>) -> Value<T> {
+        let data = if cond.data() > T::zero() { a.data() } else { b.data() };
+        if !is_grad_enabled() {
+            return Value::_no_grad("select", data, || vec![cond.clone(), a.clone(), b.clone()]);
+        }
+        Value::new(ValueData::new(
+            data,
+            Some("select"),
+            vec![cond.clone(), a.clone(), b.clone()],
+            Some(Value::_select_backward),
+        ))
+    }
+
+    fn _select_backward(out: &Value<T>) {
+        let prev = &out.prev;
+        let picked = if prev[0].data() > T::zero() { 1 } else { 2 };
+        *prev[picked].grad.borrow_mut() += out.grad();
+    }
+
+    // Largest of the values, None if there are none. Built out of max, so on a tie the gradient is split
+    // between the last value and the one that won among those before it.
+    pub fn max_of<I: IntoIterator<Item = Value<T>>>(values: I) -> Option<Value<T>> {
+        values.into_iter().reduce(|a, b| Value::max(&a, &b))
+    }
+
+    pub fn min_of<I: IntoIterator<Item = Value<T>>>(values: I) -> Option<Value<T>> {
+        values.into_iter().reduce(|a, b| Value::min(&a, &b))
+    }
+
+    // Index of the largest data, the first one on a tie and never a NaN. Not differentiable, for things
+    // like accuracy from the outputs of MLP::forward.
+    pub fn argmax(values: &[Value<T>]) -> Option<usize> {
+        let mut best: Option<(usize, T)> = None;
+        for (i, x) in values.iter().map(|v| v.data()).enumerate() {
+            if !x.is_nan() && best.is_none_or(|(_, max)| x > max) {
+                best = Some((i, x));
+            }
+        }
+        best.map(|(i, _)| i)
     }
 
-    pub fn adjust(&self, val: f32) {
+    pub fn apply<O: CustomOp<T> + 'static>(op: O, inputs: &[&Value<T>]) -> Value<T> {
+        let data = op.forward(&inputs.iter().map(|v| v.data()).collect::<Vec<T>>());
+        if !is_grad_enabled() {
+            return Value::_no_grad(op.name(), data, || inputs.iter().map(|&v| v.clone()).collect());
+        }
+        let inputs = inputs.iter().map(|&v| v.clone()).collect();
+        let op: Ptr<dyn CustomOp<T>> = Ptr::new(op);
+        Value::new(ValueData::new(data, Some(op.name()), inputs, Some(Value::_custom_backward)).with_custom(Some(op)))
+    }
+
+    pub(crate) fn _custom_backward(out: &Value<T>) {
+        let op = out.custom().unwrap();
+        let prev = &out.prev;
+        let inputs: Vec<T> = prev.iter().map(|v| v.data()).collect();
+        let grads = op.backward(&inputs, out.data(), out.grad());
+        assert_eq!(grads.len(), inputs.len(), "{} returned the wrong number of gradients", op.name());
+        prev.iter().zip(grads).for_each(|(v, g)| *v.grad.borrow_mut() += g);
+    }
+
+    // A node exactly as it was saved, data and grad included, nothing is recomputed or checked
+    pub(crate) fn _from_parts(
+        data: T,
+        grad: T,
+        op: Option<&'static str>,
+        prev: Vec<Value<T>>,
+        _backward: Option<Backward<T>>,
+        custom: Option<Ptr<dyn CustomOp<T>>>,
+    ) -> Value<T> {
+        let mut node = ValueData::new(data, op, prev, _backward).with_custom(custom);
+        node.grad = Cell::new(grad);
+        Value(Ptr::new(node))
+    }
+
+    // New leaf with the same data, gradients stop here
+    pub fn detach(&self) -> Value<T> {
+        Value::from(self.data())
+    }
+
+    pub fn adjust(&self, val: T) {
         let data = &self.0.data;
         let grad = &self.0.grad;
         *data.borrow_mut() += val * *grad.borrow();
     }
 
-    pub fn sub(a: &Value, b: &Value) -> Self {
-        Value::add(a, &Value::mul(b, &Value::from(-1.0)))
+    pub fn sub(a: &Value<T>, b: &Value<T>) -> Self {
+        Value::add(a, &Value::mul(b, &Value::from(-T::one())))
     }
 
-    pub fn div(a: &Value, b: &Value) -> Self {
+    pub fn div(a: &Value<T>, b: &Value<T>) -> Self {
         Value::mul(a, &b.powneg())
     }
 
-    pub fn pow(&self, b: &Value) -> Value {
-        Value::pow_op(self, b)
+    // Fallible versions of the ops that give NaN or inf outside of where they are defined
+    pub fn try_div(a: &Value<T>, b: &Value<T>) -> std::result::Result<Value<T>, Error> {
+        b._check_nonzero("div")?;
+        Ok(Value::div(a, b))
+    }
+
+    pub fn try_powneg(&self) -> std::result::Result<Value<T>, Error> {
+        self._check_nonzero("powneg")?;
+        Ok(self.powneg())
     }
 
-    pub fn matmul<const M: usize, const N: usize>(a: &[[Value; N]; M], b: &[Value; N]) -> [Value; M] {
+    pub fn try_ln(&self) -> std::result::Result<Value<T>, Error> {
+        self._check_domain("ln", self.data() > T::zero())?;
+        Ok(self.ln())
+    }
+
+    pub fn try_log1p(&self) -> std::result::Result<Value<T>, Error> {
+        self._check_domain("log1p", self.data() > -T::one())?;
+        Ok(self.log1p())
+    }
+
+    pub fn try_sqrt(&self) -> std::result::Result<Value<T>, Error> {
+        self._check_domain("sqrt", self.data() >= T::zero())?;
+        Ok(self.sqrt())
+    }
+
+    // A NaN divisor is a domain error, like a NaN input to the other try_ ops
+    fn _check_nonzero(&self, op: &'static str) -> std::result::Result<(), Error> {
+        if self.data() == T::zero() {
+            return Err(Error::DivisionByZero);
+        }
+        self._check_domain(op, !self.data().is_nan())
+    }
+
+    // ok is false for NaN too, since every comparison with it is
+    fn _check_domain(&self, op: &'static str, ok: bool) -> std::result::Result<(), Error> {
+        if !ok {
+            return Err(Error::Domain { op, input: self.data().to_f64().unwrap_or(f64::NAN) });
+        }
+        Ok(())
+    }
+
+    pub fn pow(&self, b: &Value<T>) -> Value<T> {
+        if b.op.is_none() {
+            Value::pow_op(self, b)
+        } else {
+            Value::pow_var(self, b)
+        }
+    }
+
+    pub fn matmul<const M: usize, const N: usize>(a: &[[Value<T>; N]; M], b: &[Value<T>; N]) -> [Value<T>; M] {
         from_fn(|i| a[i].iter().zip(b.iter()).map(|(a, b)| a * b).reduce(|a, b| a + b).unwrap())
     }
 
-    pub fn matadd<const N: usize, const M: usize>(a: &[[Value; N]; M], b: &[[Value; N]; M]) -> [[Value; N]; M] {
+    pub fn matadd<const N: usize, const M: usize>(a: &[[Value<T>; N]; M], b: &[[Value<T>; N]; M]) -> [[Value<T>; N]; M] {
         from_fn(|i| from_fn(|j| &a[i][j] + &b[i][j]))
     }
 
-    pub fn matmul_add<const P: usize, const N: usize>(a: &[[Value; P]; N], b: &[Value; P], c: &[Value; N]) -> [Value; N] {
+    pub fn matmul_add<const P: usize, const N: usize>(a: &[[Value<T>; P]; N], b: &[Value<T>; P], c: &[Value<T>; N]) -> [Value<T>; N] {
         from_fn(|i| &a[i].iter().zip(b.iter()).map(|(a, b)| a * b).reduce(|a, b| a + b).unwrap() + &c[i])
     }
 
-    pub fn activate<const I: usize>(a: [Value; I], b: &Activations) -> [Value; I] {
+    pub fn activate<const I: usize>(a: [Value<T>; I], b: &Activations) -> [Value<T>; I] {
         match b {
             Activations::Linear => a,
             Activations::Tanh => from_fn(|i| a[i].tanh()),
             Activations::Relu => from_fn(|i| a[i].relu()),
+            Activations::Sigmoid => from_fn(|i| a[i].sigmoid()),
+            Activations::LeakyRelu(slope) => from_fn(|i| a[i].leaky_relu(T::from(*slope).unwrap())),
+            Activations::Elu(alpha) => from_fn(|i| a[i].elu(T::from(*alpha).unwrap())),
+            Activations::Gelu => from_fn(|i| a[i].gelu()),
+            Activations::Silu => from_fn(|i| a[i].silu()),
+            Activations::Softplus => from_fn(|i| a[i].softplus()),
         }
     }
 
     pub fn sum<I: Iterator<Item = Self>>(mut iter: I) -> Self {
-        let mut sum = Value::from(0.0);
+        let mut sum = Value::from(T::zero());
         loop {
             let val = iter.next();
             if val.is_none() {
@@ -185,30 +748,191 @@ impl Value {
     }
 
     pub fn backward(&self) {
-        let mut topo: Vec<Value> = vec![];
-        let mut visited: HashSet<Value> = HashSet::new();
-        self._build_topo(&mut topo, &mut visited);
+        self.backward_with(T::one());
+    }
+
+    // Backward with seed as the gradient of self instead of 1, for when self feeds into something outside the graph
+    pub fn backward_with(&self, seed: T) {
+        let mut topo = self._build_topo();
         topo.reverse();
 
-        *self.0.grad.borrow_mut() = 1.0;
+        *self.0.grad.borrow_mut() = seed;
         topo.iter().for_each(|v| {
+            // Every node that feeds into v comes before it, so its gradient is final here
+            if let Some(extra) = v.extra.get() {
+                // Cloned out so a hook can register or clear hooks on v while it runs
+                let hooks = extra.hooks.borrow().clone();
+                if !hooks.is_empty() {
+                    // Read and written under one lock, so an add from another thread can't land in between
+                    let mut grad = v.grad.borrow_mut();
+                    *grad = hooks.iter().fold(*grad, |g, hook| hook(g));
+                }
+            }
             if let Some(backprop) = v._backward {
-                backprop(&v);
+                backprop(v);
+            }
+            if is_anomaly_enabled() {
+                self._check_backward(v);
             }
         });
     }
 
-    fn _build_topo(&self, topo: &mut Vec<Value>, visited: &mut HashSet<Value>) {
-        if visited.insert(self.clone()) {
-            self.prev.iter().for_each(|child| {
-                child._build_topo(topo, visited);
-            });
-            topo.push(self.clone());
+    // Hooks run in the order they were registered, each one gets what the previous returned.
+    // On a leaf the gradient seen includes earlier backward calls that weren't zeroed, grad_graph ignores hooks.
+    // The node's grad is locked while its hooks run, a hook works on the gradient it is given instead.
+    pub fn register_hook(&self, hook: impl HookFn<T>) {
+        let extra = self.extra.get_or_init(|| {
+            Box::new(Extra {
+                custom: None,
+                hooks: RefCell::new(Vec::new()),
+            })
+        });
+        extra.hooks.borrow_mut().push(Ptr::new(hook));
+    }
+
+    pub fn clear_hooks(&self) {
+        if let Some(extra) = self.extra.get() {
+            extra.hooks.borrow_mut().clear();
+        }
+    }
+
+    // backward adds into every node it reaches, so run this before calling it again on the same graph
+    pub fn zero_grad_graph(&self) {
+        self._build_topo().iter().for_each(|v| v.zero_grad());
+    }
+
+    // Gives up self for a leaf with the same data and grad once backward is done with the graph. Every node
+    // only this graph was holding on to is dropped, nodes still held elsewhere keep their links, so other
+    // graphs sharing them are left as they were.
+    pub fn free_graph(self) -> Value<T> {
+        let leaf = Value::from(self.data());
+        *leaf.grad.borrow_mut() = self.grad();
+        leaf
+    }
+
+    // Gradients of self with respect to inputs as new Values instead of numbers in grad.
+    // They are built from the same ops, so they can be differentiated again for Hessians or gradient penalties.
+    pub fn grad_graph(&self, inputs: &[&Value<T>]) -> Vec<Value<T>> {
+        let mut topo = self._build_topo();
+        topo.reverse();
+
+        let mut grads: HashMap<Value<T>, Value<T>> = HashMap::new();
+        grads.insert(self.clone(), Value::from(T::one()));
+        for v in topo.iter() {
+            let Some(g) = grads.get(v).cloned() else {
+                continue;
+            };
+            if v.op.is_none() {
+                continue;
+            }
+            let local = v._grad_graph_op(&g).unwrap_or_else(|| v._grad_graph_other(&g));
+            for (child, lg) in v.prev.iter().zip(local) {
+                let acc = match grads.remove(child) {
+                    Some(acc) => acc + lg,
+                    None => lg,
+                };
+                grads.insert(child.clone(), acc);
+            }
+        }
+        inputs.iter().map(|&x| grads.get(x).cloned().unwrap_or_else(|| Value::from(T::zero()))).collect()
+    }
+
+    // Ops that are not in define_ops. Custom ops only give numbers back, which can't be differentiated again.
+    fn _grad_graph_other(&self, g: &Value<T>) -> Vec<Value<T>> {
+        let prev = &self.prev;
+        if self.custom().is_some() {
+            panic!("no differentiable backward for op {:?}", self.op);
+        }
+        match self.op {
+            Some("clamp") => {
+                let (x, lo, hi) = (prev[0].data(), prev[1].data(), prev[2].data());
+                vec![g * &lit(if lo <= x && x <= hi { 1.0 } else { 0.0 })]
+            }
+            Some("select") => {
+                let zero = lit(0.0);
+                vec![g * &zero, Value::select(&prev[0], g, &zero), Value::select(&prev[0], &zero, g)]
+            }
+            op => panic!("no differentiable backward for op {op:?}"),
+        }
+    }
+
+    fn _describe(inputs: &[Value<T>]) -> String {
+        let inputs: Vec<String> = inputs.iter().map(|x| format!("{}({})", x.op.unwrap_or("leaf"), x.data())).collect();
+        format!("[{}]", inputs.join(", "))
+    }
+
+    // Called after v's backward step, every input should have a finite grad now
+    fn _check_backward(&self, v: &Value<T>) {
+        let bad = v.prev.iter().position(|x| !x.grad().is_finite());
+        if let Some(i) = bad {
+            let grad = v.prev[i].grad();
+            let path: Vec<&str> = self._path_to(v).iter().map(|x| x.op.unwrap_or("leaf")).collect();
+            panic!(
+                "anomaly in backward: {} gave grad {} to input {} from inputs {} and out grad {}, path from the root: {}",
+                v.op.unwrap_or("leaf"),
+                grad,
+                i,
+                Value::_describe(&v.prev),
+                v.grad(),
+                path.join(" -> ")
+            );
+        }
+    }
+
+    // Nodes from self down to target following prev, found with a depth first search on a stack
+    fn _path_to(&self, target: &Value<T>) -> Vec<Value<T>> {
+        let mut parent: HashMap<Value<T>, Value<T>> = HashMap::new();
+        let mut stack = vec![self.clone()];
+        while let Some(v) = stack.pop() {
+            if v == *target {
+                break;
+            }
+            for child in v.prev.iter() {
+                if *child != *self && !parent.contains_key(child) {
+                    parent.insert(child.clone(), v.clone());
+                    stack.push(child.clone());
+                }
+            }
+        }
+        let mut path = vec![target.clone()];
+        while let Some(p) = parent.get(path.last().unwrap()) {
+            path.push(p.clone());
+        }
+        path.reverse();
+        path
+    }
+
+    // Post-order walk on an explicit stack, so deep graphs can't overflow the call stack.
+    // A node is pushed a second time (expanded = true) below its children and emitted when popped again.
+    pub(crate) fn _build_topo(&self) -> Vec<Value<T>> {
+        let mut topo: Vec<Value<T>> = vec![];
+        let mut visited: HashSet<Value<T>> = HashSet::new();
+        let mut stack: Vec<(Value<T>, bool)> = vec![(self.clone(), false)];
+        while let Some((v, expanded)) = stack.pop() {
+            if expanded {
+                topo.push(v);
+            } else if visited.insert(v.clone()) {
+                stack.push((v.clone(), true));
+                v.prev.iter().rev().filter(|child| !visited.contains(*child)).for_each(|child| stack.push((child.clone(), false)));
+            }
+        }
+        topo
+    }
+}
+
+// The default drop recurses through prev once per node, unlink the chain on a stack instead
+impl<T: Float> Drop for ValueData<T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.prev);
+        while let Some(v) = stack.pop() {
+            if let Ok(mut data) = Ptr::try_unwrap(v.0) {
+                stack.append(&mut data.prev);
+            }
         }
     }
 }
 
-impl Debug for Value {
+impl<T: Float> Debug for Value<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "Value(data={}, grad={})", self.data(), self.grad())
     }
@@ -219,31 +943,44 @@ impl Debug for Value {
 Rust requires this boilerplate for stuff like hashset, derefrenceing into etc.
 ----------------------------------------------------------------------------------
 */
-impl ops::Deref for Value {
-    type Target = Rc<ValueData>;
+impl<T: Float> ops::Deref for Value<T> {
+    type Target = Ptr<ValueData<T>>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-impl<T: Into<f32>> From<T> for Value {
+impl<T: Float> From<T> for Value<T> {
     fn from(t: T) -> Self {
-        Value::new(ValueData::new(t.into(), None, Vec::new(), None))
+        Value::new(ValueData::new(t, None, Vec::new(), None))
     }
 }
 
-impl PartialEq for Value {
+// Integers give the default f32 Value, so an integer literal like Value::from(2) works without a type
+macro_rules! from_int {
+    ($($int:ty),*) => {
+        $(impl From<$int> for Value<f32> {
+            fn from(i: $int) -> Self {
+                Value::from(i as f32)
+            }
+        })*
+    };
+}
+
+from_int!(i8, i16, i32, u8, u16, u32);
+
+impl<T: Float> PartialEq for Value<T> {
     fn eq(&self, other: &Self) -> bool {
-        Rc::ptr_eq(&self.0, &other.0)
+        Ptr::ptr_eq(&self.0, &other.0)
     }
 }
 
-impl Eq for Value {}
+impl<T: Float> Eq for Value<T> {}
 
-impl Hash for Value {
+impl<T: Float> Hash for Value<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        Rc::as_ptr(&self.0).hash(state);
+        Ptr::as_ptr(&self.0).hash(state);
     }
 }
 
@@ -255,15 +992,15 @@ This allows us to use Value + Value instead of Value.add(Value), it works just l
 macro_rules! impl_ops {
     ( $( $Trait:ident::$method:ident ),* $(,)? ) => {
         $(
-            impl ::std::ops::$Trait<Value> for Value {
-                type Output = Value;
-                fn $method(self, other: Value) -> Self::Output {
+            impl<T: Float> ::std::ops::$Trait<Value<T>> for Value<T> {
+                type Output = Value<T>;
+                fn $method(self, other: Value<T>) -> Self::Output {
                     Value::$method(&self, &other)
                 }
             }
-            impl<'a,'b> ::std::ops::$Trait<&'b Value> for &'a Value {
-                type Output = Value;
-                fn $method(self, other: &'b Value) -> Self::Output {
+            impl<'a,'b, T: Float> ::std::ops::$Trait<&'b Value<T>> for &'a Value<T> {
+                type Output = Value<T>;
+                fn $method(self, other: &'b Value<T>) -> Self::Output {
                     Value::$method(self, other)
                 }
             }
@@ -272,20 +1009,20 @@ macro_rules! impl_ops {
 }
 impl_ops!(Add::add, Sub::sub, Mul::mul, Div::div);
 
-impl ::std::ops::Neg for Value {
-    type Output = Value;
+impl<T: Float> ::std::ops::Neg for Value<T> {
+    type Output = Value<T>;
     fn neg(self) -> Self::Output {
-        Value::mul(&self, &Value::from(-1.0))
+        Value::mul(&self, &Value::from(-T::one()))
     }
 }
-impl<'a> ::std::ops::Neg for &'a Value {
-    type Output = Value;
+impl<T: Float> ::std::ops::Neg for &Value<T> {
+    type Output = Value<T>;
     fn neg(self) -> Self::Output {
-        Value::mul(self, &Value::from(-1.0))
+        Value::mul(self, &Value::from(-T::one()))
     }
 }
 
-impl Sum for Value {
+impl<T: Float> Sum for Value<T> {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
         Value::sum(iter)
     }
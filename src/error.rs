@@ -0,0 +1,39 @@
+use crate::serialize::LoadError;
+use std::fmt::{Display, Formatter, Result};
+
+// What the try_ functions return instead of panicking or giving NaN
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    // An op given an input outside of where it is defined, like ln of a negative number
+    Domain { op: &'static str, input: f64 },
+    DivisionByZero,
+    // A slice passed where an array of a fixed size is needed
+    InputSize { expected: usize, found: usize },
+    Load(LoadError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Error::Domain { op, input } => write!(f, "{op} is not defined for {input}"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::InputSize { expected, found } => write!(f, "expected {expected} inputs, got {found}"),
+            Error::Load(e) => write!(f, "could not load graph: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Load(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<LoadError> for Error {
+    fn from(e: LoadError) -> Self {
+        Error::Load(e)
+    }
+}
@@ -0,0 +1,208 @@
+/*
+----------------------------------------------------------------------------------
+Prints a Value graph as a formula. The graph is read back through op and prev into a
+small expression tree, which is printed as infix text or LaTeX. Leaves print as their
+data unless they are given a name, naming an inner node prints the name in place of
+everything under it. Subtraction and division print as - and / even though the graph
+stores them as a multiplication by -1 and by a power of -1.
+
+Shared nodes are printed once for every path to them, so the text of a deep graph can
+grow much faster than the graph itself. Naming the shared nodes keeps it short.
+----------------------------------------------------------------------------------
+*/
+use crate::engine::{Float, Value};
+use std::collections::HashMap;
+
+enum Expr<'a, T> {
+    Var(&'a str),
+    Num(T),
+    Neg(Box<Expr<'a, T>>),
+    // Ops of the graph plus "-" and "/", which it has no nodes for
+    Op(&'static str, Vec<Expr<'a, T>>),
+}
+
+pub struct Printer<'a, T: Float = f32> {
+    names: HashMap<&'a Value<T>, &'a str>,
+    simplify: bool,
+}
+
+impl<T: Float> Value<T> {
+    // Infix text of the graph under self, like relu((2*3)+1), with every leaf printed as its data
+    pub fn to_expr_string(&self) -> String {
+        Printer::new().infix(self)
+    }
+
+    pub fn to_latex(&self) -> String {
+        Printer::new().latex(self)
+    }
+}
+
+impl<'a, T: Float> Printer<'a, T> {
+    pub fn new() -> Self {
+        Printer {
+            names: HashMap::new(),
+            simplify: false,
+        }
+    }
+
+    // Prints v as name, for a leaf that is a variable or an inner node that would be too long to spell out
+    pub fn name(mut self, v: &'a Value<T>, name: &'a str) -> Self {
+        self.names.insert(v, name);
+        self
+    }
+
+    // Prints everything under a node without named leaves as the node's data and leaves out x*1, x+0, x*0 and x^1
+    pub fn simplify(mut self) -> Self {
+        self.simplify = true;
+        self
+    }
+
+    pub fn infix(&self, v: &Value<T>) -> String {
+        self.expr(v, self.simplify).infix()
+    }
+
+    pub fn latex(&self, v: &Value<T>) -> String {
+        self.expr(v, self.simplify).latex()
+    }
+
+    // Derivative of v with respect to wrt, built with grad_graph and always simplified
+    pub fn derivative(&self, v: &Value<T>, wrt: &Value<T>) -> String {
+        self.expr(&v.grad_graph(&[wrt])[0], true).infix()
+    }
+
+    pub fn derivative_latex(&self, v: &Value<T>, wrt: &Value<T>) -> String {
+        self.expr(&v.grad_graph(&[wrt])[0], true).latex()
+    }
+
+    fn expr(&self, v: &Value<T>, simplify: bool) -> Expr<'a, T> {
+        if let Some(name) = self.names.get(v) {
+            return Expr::Var(name);
+        }
+        let Some(op) = v.op else {
+            return Expr::Num(v.data());
+        };
+        let mut args: Vec<Expr<'a, T>> = v.prev.iter().map(|x| self.expr(x, simplify)).collect();
+        if simplify && args.iter().all(|a| matches!(a, Expr::Num(_))) {
+            return Expr::Num(v.data());
+        }
+        match (op, args.len()) {
+            ("+", 2) | ("*", 2) => {
+                let (b, a) = (args.pop().unwrap(), args.pop().unwrap());
+                if op == "+" {
+                    Expr::add(a, b, simplify)
+                } else {
+                    Expr::mul(a, b, simplify)
+                }
+            }
+            ("^-", 1) => Expr::Op("/", vec![Expr::Num(T::one()), args.pop().unwrap()]),
+            ("^v" | "^i" | "^f", 2) if simplify && args[1].is(T::one()) => args.swap_remove(0),
+            _ => Expr::Op(op, args),
+        }
+    }
+}
+
+impl<T: Float> Default for Printer<'_, T> {
+    fn default() -> Self {
+        Printer::new()
+    }
+}
+
+impl<'a, T: Float> Expr<'a, T> {
+    fn is(&self, x: T) -> bool {
+        matches!(self, Expr::Num(n) if *n == x)
+    }
+
+    // A 0 on the left is left out even without simplify, Value::sum starts every sum with it
+    fn add(a: Self, b: Self, simplify: bool) -> Self {
+        let zero = |e: &Self| e.is(T::zero());
+        match (a, b) {
+            (a, b) if zero(&a) => b,
+            (a, b) if simplify && zero(&b) => a,
+            (a, Expr::Neg(b)) => Expr::Op("-", vec![a, *b]),
+            (a, b) => Expr::Op("+", vec![a, b]),
+        }
+    }
+
+    fn mul(a: Self, b: Self, simplify: bool) -> Self {
+        match (a, b) {
+            (a, b) if b.is(-T::one()) => Expr::neg(a, simplify),
+            (a, b) if a.is(-T::one()) => Expr::neg(b, simplify),
+            (a, b) if simplify && (a.is(T::zero()) || b.is(T::zero())) => Expr::Num(T::zero()),
+            (a, b) if simplify && a.is(T::one()) => b,
+            (a, b) if simplify && b.is(T::one()) => a,
+            (a, Expr::Op("/", mut d)) if d[0].is(T::one()) => Expr::Op("/", vec![a, d.pop().unwrap()]),
+            (Expr::Op("/", mut d), b) if d[0].is(T::one()) => Expr::Op("/", vec![b, d.pop().unwrap()]),
+            (a, b) => Expr::Op("*", vec![a, b]),
+        }
+    }
+
+    fn neg(x: Self, simplify: bool) -> Self {
+        match x {
+            Expr::Neg(x) if simplify => *x,
+            x => Expr::Neg(Box::new(x)),
+        }
+    }
+
+    // How tightly the expression binds, anything starting with a sign counts as a sum
+    fn prec(&self) -> u8 {
+        match self {
+            Expr::Num(n) if n.is_sign_negative() => 1,
+            Expr::Neg(_) | Expr::Op("+" | "-", _) => 1,
+            Expr::Op("*" | "/", _) => 2,
+            Expr::Op("^" | "^v" | "^i" | "^f" | "exp", _) => 3,
+            _ => 4,
+        }
+    }
+
+    // Every operand that isn't a name, a number or a call gets parentheses
+    fn infix(&self) -> String {
+        let wrap = |e: &Self| {
+            if e.prec() < 3 || matches!(e, Expr::Op("^" | "^v" | "^i" | "^f", _)) {
+                format!("({})", e.infix())
+            } else {
+                e.infix()
+            }
+        };
+        match self {
+            Expr::Var(name) => name.to_string(),
+            Expr::Num(n) => format!("{n}"),
+            Expr::Neg(x) => format!("-{}", wrap(x)),
+            Expr::Op(op @ ("+" | "-" | "*" | "/" | "^"), args) => format!("{}{op}{}", wrap(&args[0]), wrap(&args[1])),
+            Expr::Op("^v" | "^i" | "^f", args) => format!("{}^{}", wrap(&args[0]), wrap(&args[1])),
+            Expr::Op(op, args) => {
+                let name = if *op == "LeakyReLU" { "leaky_relu".to_string() } else { op.to_lowercase() };
+                format!("{name}({})", args.iter().map(Expr::infix).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+
+    fn latex(&self) -> String {
+        let wrap = |e: &Self, prec: u8| if e.prec() < prec { format!("\\left({}\\right)", e.latex()) } else { e.latex() };
+        match self {
+            Expr::Var(name) => name.to_string(),
+            Expr::Num(n) => format!("{n}"),
+            Expr::Neg(x) => format!("-{}", wrap(x, 2)),
+            Expr::Op("+", args) if args[1].prec() == 1 && !matches!(args[1], Expr::Op(..)) => {
+                format!("{} + \\left({}\\right)", args[0].latex(), args[1].latex())
+            }
+            Expr::Op("+", args) => format!("{} + {}", args[0].latex(), args[1].latex()),
+            Expr::Op("-", args) => format!("{} - {}", args[0].latex(), wrap(&args[1], 2)),
+            Expr::Op("*", args) => format!("{} \\cdot {}", wrap(&args[0], 2), wrap(&args[1], 2)),
+            Expr::Op("/", args) => format!("\\frac{{{}}}{{{}}}", args[0].latex(), args[1].latex()),
+            Expr::Op("^" | "^v" | "^i" | "^f", args) => format!("{}^{{{}}}", wrap(&args[0], 4), args[1].latex()),
+            Expr::Op("exp", args) => format!("e^{{{}}}", args[0].latex()),
+            Expr::Op("sqrt", args) => format!("\\sqrt{{{}}}", args[0].latex()),
+            Expr::Op("abs", args) => format!("\\left|{}\\right|", args[0].latex()),
+            Expr::Op("log1p", args) => format!("\\ln\\left(1 + {}\\right)", args[0].latex()),
+            Expr::Op(op, args) => {
+                let name = match *op {
+                    "log" => "\\ln".to_string(),
+                    "sigmoid" => "\\sigma".to_string(),
+                    "tanh" | "sin" | "cos" | "tan" | "max" | "min" => format!("\\{op}"),
+                    op => format!("\\operatorname{{{op}}}"),
+                };
+                format!("{name}\\left({}\\right)", args.iter().map(Expr::latex).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::engine::{no_grad, Float, Value};
+use std::fmt::{Display, Formatter, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mismatch<T: Float = f32> {
+    pub input: usize,
+    pub analytic: T,
+    pub numeric: T,
+}
+
+// Compares the gradients backward puts into `inputs` with central finite differences of f.
+// The inputs are perturbed in place and restored afterwards, their grads are overwritten.
+// The perturbed runs are under no_grad, an f that needs a graph itself wraps that part in enable_grad.
+// A gradient passes when |analytic - numeric| <= tol * max(1, |analytic|, |numeric|).
+pub fn gradcheck<T: Float, F: Fn(&[Value<T>]) -> Value<T>>(f: F, inputs: &[Value<T>], eps: T, tol: T) -> std::result::Result<(), Vec<Mismatch<T>>> {
+    inputs.iter().for_each(|x| x.zero_grad());
+    f(inputs).backward();
+
+    let two = T::one() + T::one();
+    let mismatches: Vec<Mismatch<T>> = inputs
+        .iter()
+        .enumerate()
+        .filter_map(|(input, x)| {
+            let orig = x.data();
+            *x.data.borrow_mut() = orig + eps;
+            let plus = no_grad(|| f(inputs).data());
+            *x.data.borrow_mut() = orig - eps;
+            let minus = no_grad(|| f(inputs).data());
+            *x.data.borrow_mut() = orig;
+
+            let (analytic, numeric) = (x.grad(), (plus - minus) / (two * eps));
+            let scale = T::one().max(analytic.abs()).max(numeric.abs());
+            // Written so a NaN on either side counts as a mismatch
+            if (analytic - numeric).abs() <= tol * scale {
+                None
+            } else {
+                Some(Mismatch { input, analytic, numeric })
+            }
+        })
+        .collect();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+impl<T: Float> Display for Mismatch<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "input {}: backward gave {}, finite differences gave {}",
+            self.input, self.analytic, self.numeric
+        )
+    }
+}
@@ -0,0 +1,34 @@
+use crate::engine::{Float, Value};
+use std::{array::from_fn, collections::HashSet};
+
+// Row i holds the gradient of output i with respect to every input, one backward pass per output.
+// Every grad in the graph is zeroed before each pass so intermediates shared between outputs don't
+// carry over. The grads are saved first and put back at the end, so the inputs and anything else
+// under the outputs, like the parameters of a model, are left as they were found.
+pub fn jacobian<T: Float, F: Fn(&[Value<T>; P]) -> [Value<T>; N], const P: usize, const N: usize>(f: F, inputs: &[Value<T>; P]) -> [[T; P]; N] {
+    let outputs = f(inputs);
+    let mut seen: HashSet<Value<T>> = HashSet::new();
+    let nodes: Vec<Value<T>> = outputs.iter().flat_map(|y| y._build_topo()).filter(|v| seen.insert(v.clone())).collect();
+    let saved: Vec<(&Value<T>, T)> = nodes.iter().chain(inputs).map(|v| (v, v.grad())).collect();
+    let zero = || nodes.iter().chain(inputs).for_each(|v| v.zero_grad());
+
+    let rows = from_fn(|i| {
+        zero();
+        outputs[i].backward();
+        from_fn(|j| inputs[j].grad())
+    });
+    saved.into_iter().for_each(|(v, g)| *v.grad.borrow_mut() = g);
+    rows
+}
+
+// Second derivatives of a scalar f. The first derivatives are built as Values with grad_graph and
+// then differentiated again through jacobian, so f can't use custom ops.
+pub fn hessian<T: Float, F: Fn(&[Value<T>; P]) -> Value<T>, const P: usize>(f: F, inputs: &[Value<T>; P]) -> [[T; P]; P] {
+    jacobian(
+        |x| {
+            let grads = f(x).grad_graph(&x.each_ref());
+            from_fn(|i| grads[i].clone())
+        },
+        inputs,
+    )
+}
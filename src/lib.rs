@@ -1,2 +1,15 @@
+pub mod cell;
+pub mod dual;
 pub mod engine;
+mod error;
+pub mod expr;
+pub mod gradcheck;
+pub mod jacobian;
 pub mod nn;
+pub mod serialize;
+pub mod stats;
+pub mod tape;
+pub mod trace;
+pub mod viz;
+
+pub use error::Error;
@@ -1,4 +1,6 @@
-use crate::engine::{Activations, Value};
+use crate::dual::Dual;
+use crate::engine::{Activations, Float, Value};
+use crate::Error;
 use rand::Rng;
 use std::{
     array::from_fn,
@@ -9,38 +11,53 @@ use std::{
 #[macro_export]
 macro_rules! mlp {
     ($layers:literal) => {
-        mlp_macro::generate_mlp!($layers);
+        mlp_macro::generate_mlp!($crate, $layers);
     };
 }
 
 // Structs
-pub struct Layer<const P: usize, const N: usize> {
-    w: [[Value; P]; N],
-    b: [Value; N],
+pub struct Layer<const P: usize, const N: usize, T: Float = f32> {
+    w: [[Value<T>; P]; N],
+    b: [Value<T>; N],
     nonlin: Activations,
 }
 
 // Implementation
-impl<const P: usize, const N: usize> Layer<P, N> {
-    pub fn new(nonlin: Activations) -> Layer<P, N> {
+impl<const P: usize, const N: usize, T: Float> Layer<P, N, T> {
+    pub fn new(nonlin: Activations) -> Layer<P, N, T> {
         Self {
-            w: from_fn(|_| from_fn(|_| Value::from(rand::thread_rng().gen_range(-1.0..=1.0)))),
-            b: from_fn(|_| Value::from(0.0)),
+            w: from_fn(|_| from_fn(|_| Value::from(T::from(rand::thread_rng().gen_range(-1.0..=1.0)).unwrap()))),
+            b: from_fn(|_| Value::from(T::zero())),
             nonlin,
         }
     }
 
-    pub fn forward(&self, x: &[Value; P]) -> [Value; N] {
-        Value::activate(Value::matmul_add::<P, N>(&self.w, &x, &self.b), &self.nonlin)
+    pub fn forward(&self, x: &[Value<T>; P]) -> [Value<T>; N] {
+        Value::activate(Value::matmul_add::<P, N>(&self.w, x, &self.b), &self.nonlin)
     }
 
-    pub fn parameters(&self) -> impl Iterator<Item = &Value> {
+    // Checks the length of x instead of taking an array, for inputs that come in as slices
+    pub fn try_forward(&self, x: &[Value<T>]) -> std::result::Result<[Value<T>; N], Error> {
+        let x: &[Value<T>; P] = x.try_into().map_err(|_| Error::InputSize { expected: P, found: x.len() })?;
+        Ok(self.forward(x))
+    }
+
+    // Forward mode pass, the weights are constants so the tangents only flow from x
+    pub fn forward_dual(&self, x: &[Dual<T>; P]) -> [Dual<T>; N] {
+        let act = from_fn(|i| {
+            let dot: Dual<T> = self.w[i].iter().zip(x).map(|(w, x)| Dual::constant(w.data()) * *x).sum();
+            dot + Dual::constant(self.b[i].data())
+        });
+        Dual::activate(act, &self.nonlin)
+    }
+
+    pub fn parameters(&self) -> impl Iterator<Item = &Value<T>> {
         self.w.iter().zip(self.b.iter()).flat_map(|(ws, b)| ws.iter().chain(once(b)))
     }
 }
 
 // Formater for print out
-impl<const P: usize, const N: usize> Debug for Layer<P, N> {
+impl<const P: usize, const N: usize, T: Float> Debug for Layer<P, N, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
@@ -49,10 +66,52 @@ impl<const P: usize, const N: usize> Debug for Layer<P, N> {
                 Activations::Relu => "ReLU",
                 Activations::Tanh => "Tanh",
                 Activations::Linear => "Linear",
+                Activations::Sigmoid => "Sigmoid",
+                Activations::LeakyRelu(_) => "LeakyReLU",
+                Activations::Elu(_) => "ELU",
+                Activations::Gelu => "GELU",
+                Activations::Silu => "SiLU",
+                Activations::Softplus => "Softplus",
             },
             N
         )
     }
 }
 
+// Gradient clipping, both take any iterator of parameters such as Layer::parameters or MLP::parameters
+// and return the global L2 norm of the gradients from before clipping, for logging
+
+// Scales every gradient by max_norm / norm when the norm is larger, which keeps their direction.
+// An inf or NaN norm leaves them as they are, the scale would turn every finite one into 0 or NaN.
+pub fn clip_grad_norm<'a, T: Float>(params: impl IntoIterator<Item = &'a Value<T>>, max_norm: T) -> T {
+    let params: Vec<&Value<T>> = params.into_iter().collect();
+    let norm = grad_norm(&params);
+    if norm.is_finite() && norm > max_norm {
+        let scale = max_norm / norm;
+        params.iter().for_each(|p| *p.grad.borrow_mut() = p.grad() * scale);
+    }
+    norm
+}
+
+// Clamps every gradient to [-limit, limit] on its own, a NaN stays NaN so it still shows up
+pub fn clip_grad_value<'a, T: Float>(params: impl IntoIterator<Item = &'a Value<T>>, limit: T) -> T {
+    let params: Vec<&Value<T>> = params.into_iter().collect();
+    let norm = grad_norm(&params);
+    for p in params {
+        let g = p.grad();
+        *p.grad.borrow_mut() = if g > limit {
+            limit
+        } else if g < -limit {
+            -limit
+        } else {
+            g
+        };
+    }
+    norm
+}
+
+fn grad_norm<T: Float>(params: &[&Value<T>]) -> T {
+    params.iter().map(|p| p.grad() * p.grad()).fold(T::zero(), |a, b| a + b).sqrt()
+}
+
 pub use mlp;
@@ -0,0 +1,365 @@
+/*
+----------------------------------------------------------------------------------
+Saves a Value graph as JSON and loads it back into live Values. Nodes are written one
+per line in topological order, the root last, each with its op, data, grad and the
+indices of its inputs, so two saved graphs can be diffed line by line. Loading keeps the
+saved data and grads as they are and looks up the backward of every op by its name,
+built-in ops are always known and custom ops have to be registered.
+
+Numbers use Rust's shortest round trip formatting so they load back bit for bit, NaN and
+the infinities are written as the strings "NaN", "inf" and "-inf". Hooks aren't saved.
+----------------------------------------------------------------------------------
+*/
+use crate::cell::Ptr;
+use crate::engine::{backward_fn, scalar_op, CustomOp, Float, Value};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{Display, Formatter, Result, Write},
+};
+
+const VERSION: &str = "1";
+// Deepest [ and { nesting the parser follows, a saved graph only needs 3
+const MAX_DEPTH: usize = 128;
+
+// Custom ops a graph may contain, found by CustomOp::name
+pub struct Registry<T: Float = f32> {
+    custom: HashMap<&'static str, Ptr<dyn CustomOp<T>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    // Byte offset into the text and what was expected there
+    Syntax(usize, &'static str),
+    Version(String),
+    // A field that is missing or has the wrong type, node is None for the top level object
+    Invalid {
+        node: Option<usize>,
+        field: &'static str,
+    },
+    UnknownOp(String),
+    // An input index that doesn't point at a node saved before this one
+    BadInput {
+        node: usize,
+        input: usize,
+    },
+    Arity {
+        node: usize,
+        op: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl<T: Float> Registry<T> {
+    pub fn new() -> Self {
+        Registry { custom: HashMap::new() }
+    }
+
+    pub fn register(mut self, op: impl CustomOp<T> + 'static) -> Self {
+        assert!(backward_fn::<T>(op.name()).is_none(), "{} is the name of a built-in op", op.name());
+        self.custom.insert(op.name(), Ptr::new(op));
+        self
+    }
+}
+
+impl<T: Float> Default for Registry<T> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+pub fn to_json<T: Float>(root: &Value<T>) -> String {
+    let topo = root._build_topo();
+    let index: HashMap<&Value<T>, usize> = topo.iter().enumerate().map(|(i, v)| (v, i)).collect();
+    let mut json = format!("{{\n  \"version\": {VERSION},\n  \"nodes\": [\n");
+    for (i, v) in topo.iter().enumerate() {
+        let op = v.op.map_or("null".to_string(), quote);
+        let prev: Vec<String> = v.prev.iter().map(|x| index[x].to_string()).collect();
+        let (data, grad) = (number(v.data()), number(v.grad()));
+        write!(
+            json,
+            "    {{\"op\": {op}, \"data\": {data}, \"grad\": {grad}, \"prev\": [{}]}}",
+            prev.join(", ")
+        )
+        .unwrap();
+        json.push_str(if i + 1 < topo.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("  ]\n}\n");
+    json
+}
+
+// Every node in the order they were saved, the root is the last one
+pub fn from_json<T: Float>(json: &str, registry: &Registry<T>) -> std::result::Result<Vec<Value<T>>, LoadError> {
+    let mut parser = Parser {
+        text: json.as_bytes(),
+        pos: 0,
+        depth: 0,
+    };
+    let top = parser.value()?;
+    parser.space();
+    if parser.pos < json.len() {
+        return Err(LoadError::Syntax(parser.pos, "end of input"));
+    }
+    match top.get("version") {
+        Some(Json::Num(v)) if v == VERSION => {}
+        Some(Json::Num(v)) => return Err(LoadError::Version(v.clone())),
+        _ => {
+            return Err(LoadError::Invalid {
+                node: None,
+                field: "version",
+            })
+        }
+    }
+    let Some(Json::Arr(nodes)) = top.get("nodes") else {
+        return Err(LoadError::Invalid { node: None, field: "nodes" });
+    };
+
+    let mut values: Vec<Value<T>> = Vec::with_capacity(nodes.len());
+    for (i, node) in nodes.iter().enumerate() {
+        let invalid = |field| LoadError::Invalid { node: Some(i), field };
+        let number = |field| node.get(field).and_then(Json::number).ok_or(invalid(field));
+        let (data, grad) = (number("data")?, number("grad")?);
+        let Some(Json::Arr(inputs)) = node.get("prev") else {
+            return Err(invalid("prev"));
+        };
+        let prev = inputs
+            .iter()
+            .map(|input| match input {
+                Json::Num(j) => match j.parse::<usize>() {
+                    Ok(j) if j < i => Ok(values[j].clone()),
+                    Ok(j) => Err(LoadError::BadInput { node: i, input: j }),
+                    Err(_) => Err(invalid("prev")),
+                },
+                _ => Err(invalid("prev")),
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let value = match node.get("op") {
+            Some(Json::Null) => Value::_from_parts(data, grad, None, prev, None, None),
+            Some(Json::Str(op)) => {
+                if let Some((op, backward)) = backward_fn::<T>(op) {
+                    let expected = scalar_op::<T>(op).unwrap().arity;
+                    if prev.len() != expected {
+                        let (op, found) = (op.to_string(), prev.len());
+                        return Err(LoadError::Arity {
+                            node: i,
+                            op,
+                            expected,
+                            found,
+                        });
+                    }
+                    Value::_from_parts(data, grad, Some(op), prev, Some(backward), None)
+                } else if let Some(custom) = registry.custom.get(op.as_str()) {
+                    Value::_from_parts(data, grad, Some(custom.name()), prev, Some(Value::_custom_backward), Some(custom.clone()))
+                } else {
+                    return Err(LoadError::UnknownOp(op.clone()));
+                }
+            }
+            _ => return Err(invalid("op")),
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+
+fn number<T: Float>(x: T) -> String {
+    if x.is_finite() {
+        format!("{x}")
+    } else {
+        format!("\"{x}\"")
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/*
+------------------------------------------------------------------------------------------------
+Just enough JSON to read back what to_json writes, or the same thing written by another tool.
+Numbers are kept as text so they can be parsed straight into T.
+------------------------------------------------------------------------------------------------
+*/
+enum Json {
+    Null,
+    Bool,
+    Num(String),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    // Arrays and objects the parser is inside of, value recurses once for each
+    depth: usize,
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Non-finite numbers are strings
+    fn number<T: Float>(&self) -> Option<T> {
+        match self {
+            Json::Num(n) | Json::Str(n) => T::from_str_radix(n, 10).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Parser<'_> {
+    fn space(&mut self) {
+        while self.text.get(self.pos).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.space();
+        let found = self.text.get(self.pos) == Some(&c);
+        self.pos += found as usize;
+        found
+    }
+
+    fn expect(&mut self, c: u8, what: &'static str) -> std::result::Result<(), LoadError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(LoadError::Syntax(self.pos, what))
+        }
+    }
+
+    // Steps into an array or object, an error rather than a stack overflow for input nested too deep
+    fn enter(&mut self) -> std::result::Result<(), LoadError> {
+        if self.depth == MAX_DEPTH {
+            return Err(LoadError::Syntax(self.pos, "at most 128 levels of nesting"));
+        }
+        self.depth += 1;
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> std::result::Result<Json, LoadError> {
+        self.space();
+        let rest = &self.text[self.pos..];
+        for (word, value) in [("null", Json::Null), ("true", Json::Bool), ("false", Json::Bool)] {
+            if rest.starts_with(word.as_bytes()) {
+                self.pos += word.len();
+                return Ok(value);
+            }
+        }
+        match rest.first() {
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b'[') => {
+                self.enter()?;
+                let mut items = vec![];
+                if !self.eat(b']') {
+                    loop {
+                        items.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',', "',' or ']'")?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Arr(items))
+            }
+            Some(b'{') => {
+                self.enter()?;
+                let mut fields = vec![];
+                if !self.eat(b'}') {
+                    loop {
+                        self.space();
+                        let key = self.string()?;
+                        self.expect(b':', "':'")?;
+                        fields.push((key, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',', "',' or '}'")?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Obj(fields))
+            }
+            Some(c) if c.is_ascii_digit() || *c == b'-' => {
+                let len = rest.iter().take_while(|c| c.is_ascii_digit() || b"+-.eE".contains(c)).count();
+                self.pos += len;
+                Ok(Json::Num(String::from_utf8_lossy(&rest[..len]).into_owned()))
+            }
+            _ => Err(LoadError::Syntax(self.pos, "a value")),
+        }
+    }
+
+    fn string(&mut self) -> std::result::Result<String, LoadError> {
+        self.expect(b'"', "a string")?;
+        let mut bytes = vec![];
+        loop {
+            let Some(&c) = self.text.get(self.pos) else {
+                return Err(LoadError::Syntax(self.pos, "'\"'"));
+            };
+            self.pos += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = self.text.get(self.pos).copied();
+                    self.pos += 1;
+                    match escaped {
+                        Some(b'u') => {
+                            let hex = self
+                                .text
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|h| u32::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+                            let c = hex.and_then(char::from_u32).ok_or(LoadError::Syntax(self.pos, "4 hex digits"))?;
+                            bytes.extend(c.to_string().as_bytes());
+                            self.pos += 4;
+                        }
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'r') => bytes.push(b'\r'),
+                        Some(b'b') => bytes.push(8),
+                        Some(b'f') => bytes.push(12),
+                        Some(c @ (b'"' | b'\\' | b'/')) => bytes.push(c),
+                        _ => return Err(LoadError::Syntax(self.pos - 1, "an escape")),
+                    }
+                }
+                c => bytes.push(c),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| LoadError::Syntax(self.pos, "UTF-8"))
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            LoadError::Syntax(pos, what) => write!(f, "expected {what} at byte {pos}"),
+            LoadError::Version(v) => write!(f, "unsupported version {v}, expected {VERSION}"),
+            LoadError::Invalid { node: None, field } => write!(f, "missing or invalid {field}"),
+            LoadError::Invalid { node: Some(i), field } => write!(f, "missing or invalid {field} in node {i}"),
+            LoadError::UnknownOp(op) => write!(f, "unknown op {op:?}, custom ops have to be registered"),
+            LoadError::BadInput { node, input } => write!(f, "node {node} has input {input}, which isn't saved before it"),
+            LoadError::Arity { node, op, expected, found } => write!(f, "node {node} is {op} with {found} inputs instead of {expected}"),
+        }
+    }
+}
+
+impl Error for LoadError {}
@@ -0,0 +1,156 @@
+/*
+----------------------------------------------------------------------------------
+Memory accounting. graph_stats walks a Value graph and estimates what it holds on the
+heap, CountingAlloc is a global allocator that counts what the whole program asks for,
+so a training loop can log both per step.
+----------------------------------------------------------------------------------
+*/
+use crate::engine::{Extra, Float, Hook, Value, ValueData};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter, Result},
+    mem::size_of,
+    ops::Sub,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    // One per entry in prev, x * x has two
+    pub edges: usize,
+    // Edges on the longest path from the root to a leaf, 0 for a lone leaf
+    pub depth: usize,
+    // Nodes per op, leaves aren't counted
+    pub ops: BTreeMap<&'static str, usize>,
+    pub heap_bytes: usize,
+}
+
+impl<T: Float> Value<T> {
+    // Heap bytes are estimated per node as the Rc (or Arc) allocation, counts included, plus the capacity of prev
+    // and, for the few nodes that have one, the box holding a custom op and hooks. What a custom op or a hook
+    // closure holds is shared or unknown and left out.
+    pub fn graph_stats(&self) -> GraphStats {
+        let node = Layout::new::<[usize; 2]>().extend(Layout::new::<ValueData<T>>()).unwrap().0.pad_to_align().size();
+        let mut stats = GraphStats {
+            nodes: 0,
+            leaves: 0,
+            edges: 0,
+            depth: 0,
+            ops: BTreeMap::new(),
+            heap_bytes: 0,
+        };
+        let mut depth: HashMap<Value<T>, usize> = HashMap::new();
+        for v in self._build_topo() {
+            let prev = &v.prev;
+            stats.nodes += 1;
+            stats.edges += prev.len();
+            stats.heap_bytes += node + prev.capacity() * size_of::<Value<T>>();
+            if let Some(extra) = v.extra.get() {
+                stats.heap_bytes += size_of::<Extra<T>>() + extra.hooks.borrow().capacity() * size_of::<Hook<T>>();
+            }
+            match v.op {
+                Some(op) => *stats.ops.entry(op).or_default() += 1,
+                None => stats.leaves += 1,
+            }
+            let d = prev.iter().map(|x| depth[x] + 1).max().unwrap_or(0);
+            depth.insert(v, d);
+        }
+        stats.depth = depth[self];
+        stats
+    }
+}
+
+impl Display for GraphStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "{} nodes ({} leaves), {} edges, depth {}, ~{} heap bytes",
+            self.nodes, self.leaves, self.edges, self.depth, self.heap_bytes
+        )?;
+        for (op, n) in &self.ops {
+            write!(f, "\n  {op}: {n}")?;
+        }
+        Ok(())
+    }
+}
+
+/*
+------------------------------------------------------------------------------------------------
+Global allocation counter. Register it in a binary with
+    #[global_allocator]
+    static GLOBAL: CountingAlloc = CountingAlloc;
+and take the difference of two CountingAlloc::count() around the code being measured. The counts
+are for the whole process, other threads included.
+------------------------------------------------------------------------------------------------
+*/
+pub struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+static FREED: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocCount {
+    pub allocations: usize,
+    pub deallocations: usize,
+    // Bytes asked for, freed ones included
+    pub bytes: usize,
+    pub freed: usize,
+}
+
+// A realloc counts as an allocation and a deallocation, like the default GlobalAlloc::realloc does it
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        FREED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+impl CountingAlloc {
+    // Totals since the program started, all 0 if CountingAlloc isn't the global allocator
+    pub fn count() -> AllocCount {
+        AllocCount {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+            bytes: BYTES.load(Ordering::Relaxed),
+            freed: FREED.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl AllocCount {
+    // Bytes allocated and not freed yet, can be negative for the difference of two counts
+    pub fn live_bytes(&self) -> isize {
+        self.bytes.wrapping_sub(self.freed) as isize
+    }
+}
+
+// What happened between two counts, later - earlier
+impl Sub for AllocCount {
+    type Output = AllocCount;
+    fn sub(self, earlier: AllocCount) -> AllocCount {
+        AllocCount {
+            allocations: self.allocations - earlier.allocations,
+            deallocations: self.deallocations - earlier.deallocations,
+            bytes: self.bytes - earlier.bytes,
+            freed: self.freed - earlier.freed,
+        }
+    }
+}
+
+impl Display for AllocCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} allocations, {} bytes, {} live bytes", self.allocations, self.bytes, self.live_bytes())
+    }
+}
@@ -0,0 +1,297 @@
+/*
+----------------------------------------------------------------------------------
+Arena version of the engine. Instead of one Rc allocation and one prev Vec per op,
+every node is pushed onto a single Vec and referred to by its u32 index. Nodes are
+appended in evaluation order, so the tape already is a topological sort and backward
+is a reverse walk with no HashSet. Truncating back to the parameters keeps the
+capacity, so after the first iteration a training step allocates nothing.
+----------------------------------------------------------------------------------
+*/
+use crate::engine::Float;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{Debug, Formatter, Result},
+    iter::Sum,
+    ops,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Leaf,
+    Add,
+    Mul,
+    Pow,
+    PowNeg,
+    Exp,
+    Ln,
+    Tanh,
+    Relu,
+}
+
+#[derive(Clone, Copy)]
+struct Node<T: Float> {
+    data: T,
+    grad: T,
+    op: Op,
+    args: [u32; 2],
+    // Generation of the tape when the node was pushed
+    gen: u32,
+}
+
+pub struct Tape<T: Float = f32> {
+    nodes: RefCell<Vec<Node<T>>>,
+    // Goes up on every truncate, so a Var can tell its index was handed to a newer node
+    gen: Cell<u32>,
+}
+
+#[derive(Clone, Copy)]
+pub struct Var<'t, T: Float = f32> {
+    tape: &'t Tape<T>,
+    idx: u32,
+    gen: u32,
+}
+
+impl<T: Float> Tape<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Tape {
+            nodes: RefCell::new(Vec::with_capacity(capacity)),
+            gen: Cell::new(0),
+        }
+    }
+
+    pub fn var(&self, data: T) -> Var<'_, T> {
+        self.push(data, Op::Leaf, [0, 0])
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.nodes.borrow().capacity()
+    }
+
+    // Drops every node from `len` on, vars pointing past it must not be used afterwards.
+    // Debug builds panic when one is.
+    pub fn truncate(&self, len: usize) {
+        self.nodes.borrow_mut().truncate(len);
+        self.gen.set(self.gen.get().wrapping_add(1));
+    }
+
+    pub fn zero_grad(&self) {
+        self.nodes.borrow_mut().iter_mut().for_each(|n| n.grad = T::zero());
+    }
+
+    fn push(&self, data: T, op: Op, args: [u32; 2]) -> Var<'_, T> {
+        let mut nodes = self.nodes.borrow_mut();
+        let idx = u32::try_from(nodes.len()).expect("tape holds at most u32::MAX nodes");
+        let gen = self.gen.get();
+        nodes.push(Node {
+            data,
+            grad: T::zero(),
+            op,
+            args,
+            gen,
+        });
+        Var { tape: self, idx, gen }
+    }
+
+    fn apply(&self, op: Op, a: Var<'_, T>, b: Var<'_, T>) -> Var<'_, T> {
+        let data = {
+            let nodes = self.nodes.borrow();
+            let (x, y) = (nodes[a.slot(&nodes)].data, nodes[b.slot(&nodes)].data);
+            match op {
+                Op::Leaf => unreachable!(),
+                Op::Add => x + y,
+                Op::Mul => x * y,
+                Op::Pow => x.powf(y),
+                Op::PowNeg => x.recip(),
+                Op::Exp => x.exp(),
+                Op::Ln => x.ln(),
+                Op::Tanh => x.tanh(),
+                Op::Relu => x.max(T::zero()),
+            }
+        };
+        self.push(data, op, [a.idx, b.idx])
+    }
+
+    fn backward(&self, root: u32) {
+        let mut nodes = self.nodes.borrow_mut();
+        nodes[root as usize].grad = T::one();
+        for i in (0..=root as usize).rev() {
+            let Node {
+                data: out,
+                grad: g,
+                op,
+                args: [a, b],
+                ..
+            } = nodes[i];
+            let (x, y) = (nodes[a as usize].data, nodes[b as usize].data);
+            let (da, db) = match op {
+                Op::Leaf => continue,
+                Op::Add => (g, g),
+                Op::Mul => (y * g, x * g),
+                // Same rule as Value::pow, d/dy is masked for a non-positive base only when y is a leaf
+                Op::Pow if x <= T::zero() && nodes[b as usize].op == Op::Leaf => (y * x.powf(y - T::one()) * g, T::zero()),
+                Op::Pow => (y * x.powf(y - T::one()) * g, out * x.ln() * g),
+                Op::PowNeg => (-x.powi(2).recip() * g, T::zero()),
+                Op::Exp => (out * g, T::zero()),
+                Op::Ln => (g / x, T::zero()),
+                Op::Tanh => ((T::one() - out * out) * g, T::zero()),
+                Op::Relu => (if out > T::zero() { g } else { T::zero() }, T::zero()),
+            };
+            nodes[a as usize].grad += da;
+            if matches!(op, Op::Add | Op::Mul | Op::Pow) {
+                nodes[b as usize].grad += db;
+            }
+        }
+    }
+}
+
+impl<T: Float> Default for Tape<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'t, T: Float> Var<'t, T> {
+    pub fn index(&self) -> u32 {
+        self.idx
+    }
+
+    pub fn op(&self) -> Op {
+        let nodes = self.tape.nodes.borrow();
+        nodes[self.slot(&nodes)].op
+    }
+
+    pub fn data(&self) -> T {
+        let nodes = self.tape.nodes.borrow();
+        nodes[self.slot(&nodes)].data
+    }
+
+    pub fn grad(&self) -> T {
+        let nodes = self.tape.nodes.borrow();
+        nodes[self.slot(&nodes)].grad
+    }
+
+    pub fn zero_grad(&self) {
+        let mut nodes = self.tape.nodes.borrow_mut();
+        let i = self.slot(&nodes);
+        nodes[i].grad = T::zero();
+    }
+
+    pub fn adjust(&self, val: T) {
+        let mut nodes = self.tape.nodes.borrow_mut();
+        let i = self.slot(&nodes);
+        let node = &mut nodes[i];
+        node.data += val * node.grad;
+    }
+
+    pub fn pow(self, b: Var<'t, T>) -> Var<'t, T> {
+        self.same_tape(b);
+        self.tape.apply(Op::Pow, self, b)
+    }
+
+    pub fn powneg(self) -> Var<'t, T> {
+        self.tape.apply(Op::PowNeg, self, self)
+    }
+
+    pub fn exp(self) -> Var<'t, T> {
+        self.tape.apply(Op::Exp, self, self)
+    }
+
+    pub fn ln(self) -> Var<'t, T> {
+        self.tape.apply(Op::Ln, self, self)
+    }
+
+    pub fn tanh(self) -> Var<'t, T> {
+        self.tape.apply(Op::Tanh, self, self)
+    }
+
+    pub fn relu(self) -> Var<'t, T> {
+        self.tape.apply(Op::Relu, self, self)
+    }
+
+    pub fn backward(&self) {
+        self.slot(&self.tape.nodes.borrow());
+        self.tape.backward(self.idx);
+    }
+
+    // Index of the node, checked in debug builds to still be the one this Var was made for and not a
+    // newer node that took its place after a truncate
+    fn slot(&self, nodes: &[Node<T>]) -> usize {
+        let i = self.idx as usize;
+        debug_assert!(nodes.get(i).is_some_and(|n| n.gen == self.gen), "Var used after Tape::truncate dropped its node");
+        i
+    }
+
+    // An index is only meaningful on the tape it came from, other's would silently read a different node
+    fn same_tape(&self, other: Var<'t, T>) {
+        assert!(std::ptr::eq(self.tape, other.tape), "Vars from different tapes can't be combined");
+    }
+}
+
+impl<T: Float> Debug for Var<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "Var(data={}, grad={})", self.data(), self.grad())
+    }
+}
+
+/*
+------------------------------------------------------------------------------------------------
+Same operator overloads as Value, Var is Copy so they take it by value
+------------------------------------------------------------------------------------------------
+*/
+impl<'t, T: Float> ops::Add for Var<'t, T> {
+    type Output = Var<'t, T>;
+    fn add(self, other: Var<'t, T>) -> Self::Output {
+        self.same_tape(other);
+        self.tape.apply(Op::Add, self, other)
+    }
+}
+
+impl<'t, T: Float> ops::Mul for Var<'t, T> {
+    type Output = Var<'t, T>;
+    fn mul(self, other: Var<'t, T>) -> Self::Output {
+        self.same_tape(other);
+        self.tape.apply(Op::Mul, self, other)
+    }
+}
+
+impl<'t, T: Float> ops::Sub for Var<'t, T> {
+    type Output = Var<'t, T>;
+    fn sub(self, other: Var<'t, T>) -> Self::Output {
+        self.same_tape(other);
+        self + -other
+    }
+}
+
+impl<'t, T: Float> ops::Div for Var<'t, T> {
+    type Output = Var<'t, T>;
+    fn div(self, other: Var<'t, T>) -> Self::Output {
+        self.same_tape(other);
+        self.tape.apply(Op::Mul, self, other.powneg())
+    }
+}
+
+impl<'t, T: Float> ops::Neg for Var<'t, T> {
+    type Output = Var<'t, T>;
+    fn neg(self) -> Self::Output {
+        self * self.tape.var(-T::one())
+    }
+}
+
+// Panics on an empty iterator, there is no tape to put the zero on
+impl<'t, T: Float> Sum for Var<'t, T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.reduce(|a, b| a + b).expect("cannot sum an empty iterator of Vars")
+    }
+}
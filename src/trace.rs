@@ -0,0 +1,359 @@
+/*
+----------------------------------------------------------------------------------
+Replays a Value graph without rebuilding it. Trace::new walks the graph once and lays
+it out as a flat list of instructions over a single data and grad Vec, in the same
+topological order backward uses. Inputs and parameters get slots that are refreshed
+on every forward, every other leaf is frozen as a constant. Forward and backward then
+run over the slots with the scalar form of each op, so a training step allocates
+nothing and gives the same numbers as the Value graph, bit for bit.
+
+Every node starts with one data slot and one grad slot of the same index. optimize
+points nodes at each other's slots, forward only runs the instructions whose result
+isn't already somewhere else, backward only skips the ones that can't change a grad.
+----------------------------------------------------------------------------------
+*/
+use crate::cell::Ptr;
+use crate::engine::{scalar_op, CustomOp, Float, ScalarOp, Value};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::{Debug, Display, Formatter, Result},
+    ops::Range,
+};
+
+pub(crate) enum Kernel<T: Float> {
+    Builtin(ScalarOp<T>),
+    Custom(Ptr<dyn CustomOp<T>>),
+}
+
+pub(crate) struct Instr<T: Float> {
+    pub(crate) op: &'static str,
+    pub(crate) kernel: Kernel<T>,
+    // Range into Trace::args for the data slots of the inputs and into Trace::grad_args for their grad slots
+    pub(crate) args: Range<usize>,
+    pub(crate) out: u32,
+    pub(crate) grad_out: u32,
+}
+
+// What Trace::optimize did, node counts include leaves and merged counts constants as well as instructions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub before: usize,
+    pub after: usize,
+    pub folded: usize,
+    pub merged: usize,
+    pub simplified: usize,
+}
+
+pub struct Trace<T: Float = f32> {
+    pub(crate) instrs: Vec<Instr<T>>,
+    // Instructions forward runs in order and backward runs in reverse
+    pub(crate) forward: Vec<usize>,
+    pub(crate) backward: Vec<usize>,
+    pub(crate) args: Vec<u32>,
+    pub(crate) grad_args: Vec<u32>,
+    pub(crate) data: Vec<T>,
+    pub(crate) grad: Vec<T>,
+    // None for an input the output doesn't depend on
+    pub(crate) inputs: Vec<Option<u32>>,
+    pub(crate) params: Vec<(Value<T>, u32)>,
+    // Data and grad slot of the output
+    pub(crate) output: (u32, u32),
+    // Arguments and local gradients of the current instruction, reused so replaying doesn't allocate
+    xs: Vec<T>,
+    gs: Vec<T>,
+}
+
+impl<T: Float> Kernel<T> {
+    fn forward(&self, xs: &[T]) -> T {
+        match self {
+            Kernel::Builtin(op) => (op.forward)(xs),
+            Kernel::Custom(op) => op.forward(xs),
+        }
+    }
+}
+
+impl<T: Float> Trace<T> {
+    // Hooks are not part of the trace, and custom ops still allocate the Vec their backward returns
+    pub fn new<'a>(output: &Value<T>, inputs: &[&Value<T>], params: impl IntoIterator<Item = &'a Value<T>>) -> Trace<T> {
+        let topo = output._build_topo();
+        let slot: HashMap<&Value<T>, u32> = topo.iter().enumerate().map(|(i, v)| (v, i as u32)).collect();
+
+        let (mut instrs, mut args) = (vec![], vec![]);
+        for (out, v) in topo.iter().enumerate() {
+            let Some(op) = v.op else {
+                continue;
+            };
+            let kernel = match v.custom() {
+                Some(custom) => Kernel::Custom(custom.clone()),
+                None => Kernel::Builtin(scalar_op(op).unwrap_or_else(|| panic!("no scalar form for op {op:?}"))),
+            };
+            let start = args.len();
+            args.extend(v.prev.iter().map(|x| slot[x]));
+            instrs.push(Instr {
+                op,
+                kernel,
+                args: start..args.len(),
+                out: out as u32,
+                grad_out: out as u32,
+            });
+        }
+
+        let width = instrs.iter().map(|i| i.args.len()).max().unwrap_or(0);
+        Trace {
+            forward: (0..instrs.len()).collect(),
+            backward: (0..instrs.len()).collect(),
+            instrs,
+            grad_args: args.clone(),
+            args,
+            data: topo.iter().map(|v| v.data()).collect(),
+            grad: vec![T::zero(); topo.len()],
+            inputs: inputs.iter().map(|&x| slot.get(x).copied()).collect(),
+            params: params.into_iter().filter_map(|p| Some((p.clone(), *slot.get(p)?))).collect(),
+            output: (slot[output], slot[output]),
+            xs: Vec::with_capacity(width),
+            gs: Vec::with_capacity(width),
+        }
+    }
+
+    // Number of instructions forward runs
+    pub fn len(&self) -> usize {
+        self.forward.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    // Leaves and instructions the output is computed from
+    pub fn nodes(&self) -> usize {
+        let mut live: HashSet<u32> = HashSet::from([self.output.0]);
+        for instr in self.forward.iter().map(|&i| &self.instrs[i]) {
+            live.extend(&self.args[instr.args.clone()]);
+            live.insert(instr.out);
+        }
+        live.len()
+    }
+
+    // Loads inputs in the order they were traced with and the current data of every parameter, returns the output
+    pub fn forward(&mut self, inputs: &[T]) -> T {
+        assert_eq!(inputs.len(), self.inputs.len(), "trace has {} inputs", self.inputs.len());
+        for (slot, x) in self.inputs.iter().zip(inputs) {
+            if let Some(slot) = slot {
+                self.data[*slot as usize] = *x;
+            }
+        }
+        for (p, slot) in self.params.iter() {
+            self.data[*slot as usize] = p.data();
+        }
+
+        let Trace {
+            instrs,
+            forward,
+            args,
+            data,
+            xs,
+            ..
+        } = self;
+        for instr in forward.iter().map(|&i| &instrs[i]) {
+            xs.clear();
+            xs.extend(args[instr.args.clone()].iter().map(|&a| data[a as usize]));
+            data[instr.out as usize] = instr.kernel.forward(xs);
+        }
+        self.output()
+    }
+
+    // Gradients of the last forward, added into the grad of every parameter like Value::backward does
+    pub fn backward(&mut self) {
+        let Trace {
+            instrs,
+            backward,
+            args,
+            grad_args,
+            data,
+            grad,
+            xs,
+            gs,
+            ..
+        } = self;
+        grad.fill(T::zero());
+        grad[self.output.1 as usize] = T::one();
+        for instr in backward.iter().rev().map(|&i| &instrs[i]) {
+            xs.clear();
+            xs.extend(args[instr.args.clone()].iter().map(|&a| data[a as usize]));
+            let (out, g) = (data[instr.out as usize], grad[instr.grad_out as usize]);
+            match &instr.kernel {
+                Kernel::Builtin(op) => {
+                    gs.resize(xs.len(), T::zero());
+                    (op.backward)(xs, out, g, gs);
+                }
+                Kernel::Custom(op) => {
+                    gs.clear();
+                    gs.extend(op.backward(xs, out, g));
+                }
+            }
+            grad_args[instr.args.clone()]
+                .iter()
+                .zip(gs.iter())
+                .for_each(|(&a, &d)| grad[a as usize] += d);
+        }
+        for (p, slot) in self.params.iter() {
+            *p.grad.borrow_mut() += self.grad[*slot as usize];
+        }
+    }
+
+    pub fn output(&self) -> T {
+        self.data[self.output.0 as usize]
+    }
+
+    // Gradient of input i from the last backward, 0 if the output doesn't depend on it
+    pub fn input_grad(&self, i: usize) -> T {
+        self.inputs[i].map_or(T::zero(), |slot| self.grad[slot as usize])
+    }
+
+    // Folds instructions whose inputs are all constants, merges equal constants and repeated (op, inputs)
+    // and drops x * 1, x + -0.0 and x + 0.0. Forward values and gradients stay bit for bit what they were,
+    // which limits each pass: a merged instruction still runs its own backward step so its gradient is added
+    // in the original order, and an identity only hands its gradient straight to x when both are used once.
+    // -0.0 + 0.0 is 0.0, so x + 0.0 is only dropped when x can't be -0.0, like the first term of a sum
+    // of squares.
+    pub fn optimize(&mut self) -> Report {
+        let n = self.data.len();
+        let mut report = Report {
+            before: self.nodes(),
+            after: 0,
+            folded: 0,
+            merged: 0,
+            simplified: 0,
+        };
+
+        // Slots whose value is fixed: leaves that are neither inputs nor params, then everything that folds
+        let mut known = vec![true; n];
+        self.instrs.iter().for_each(|i| known[i.out as usize] = false);
+        let variables = self.inputs.iter().flatten().chain(self.params.iter().map(|(_, slot)| slot));
+        variables.for_each(|&slot| known[slot as usize] = false);
+
+        // Slots that may hold -0.0, filled in for each instruction as it is reached
+        let mut neg_zero: Vec<bool> = (0..n).map(|i| !known[i] || self.data[i] == T::zero() && self.data[i].is_sign_negative()).collect();
+
+        // How many times backward adds into each grad slot, the seed counts as one
+        let mut uses = vec![0usize; n];
+        for instr in self.backward.iter().map(|&i| &self.instrs[i]) {
+            self.grad_args[instr.args.clone()].iter().for_each(|&a| uses[a as usize] += 1);
+        }
+        uses[self.output.1 as usize] += 1;
+
+        let mut data_slot: Vec<u32> = (0..n as u32).collect();
+        let mut grad_slot = data_slot.clone();
+        let mut constants: HashMap<(u64, i16, i8), u32> = HashMap::new();
+        for (slot, _) in known.iter().enumerate().filter(|(_, &k)| k) {
+            data_slot[slot] = *constants.entry(self.data[slot].integer_decode()).or_insert(slot as u32);
+            report.merged += (data_slot[slot] != slot as u32) as usize;
+        }
+
+        let in_forward: HashSet<usize> = self.forward.iter().copied().collect();
+        let mut seen: HashMap<(&'static str, usize, Vec<u32>), u32> = HashMap::new();
+        let (mut forward, mut backward) = (vec![], vec![]);
+        for &i in self.backward.iter() {
+            let range = self.instrs[i].args.clone();
+            self.args[range.clone()].iter_mut().for_each(|a| *a = data_slot[*a as usize]);
+            self.grad_args[range.clone()].iter_mut().for_each(|a| *a = grad_slot[*a as usize]);
+            let (instr, args) = (&mut self.instrs[i], &self.args[range.clone()]);
+            let out = instr.out as usize;
+            if !in_forward.contains(&i) {
+                backward.push(i);
+                continue;
+            }
+
+            if args.iter().all(|&a| known[a as usize]) {
+                self.xs.clear();
+                self.xs.extend(args.iter().map(|&a| self.data[a as usize]));
+                self.data[out] = instr.kernel.forward(&self.xs);
+                known[out] = true;
+                neg_zero[out] = self.data[out] == T::zero() && self.data[out].is_sign_negative();
+                data_slot[out] = *constants.entry(self.data[out].integer_decode()).or_insert(out as u32);
+                report.folded += 1;
+                continue;
+            }
+
+            let is =
+                |a: u32, v: T| known[a as usize] && self.data[a as usize] == v && self.data[a as usize].is_sign_negative() == v.is_sign_negative();
+            let identity = match (instr.op, args, &instr.kernel) {
+                ("*", &[_, b], Kernel::Builtin(_)) if is(b, T::one()) => Some(0),
+                ("*", &[a, _], Kernel::Builtin(_)) if is(a, T::one()) => Some(1),
+                ("+", &[a, b], Kernel::Builtin(_)) if is(b, -T::zero()) || is(b, T::zero()) && !neg_zero[a as usize] => Some(0),
+                ("+", &[a, b], Kernel::Builtin(_)) if is(a, -T::zero()) || is(a, T::zero()) && !neg_zero[b as usize] => Some(1),
+                _ => None,
+            };
+            // Only a sum of two -0.0 is -0.0, and these ops never give it whatever their input
+            neg_zero[out] = match (instr.op, args, &instr.kernel) {
+                (_, _, Kernel::Custom(_)) => true,
+                ("+", &[a, b], _) => neg_zero[a as usize] && neg_zero[b as usize],
+                ("exp" | "sigmoid" | "softplus" | "abs", _, _) => false,
+                ("^i", &[_, n], _) => !(known[n as usize] && self.data[n as usize] % (T::one() + T::one()) == T::zero()),
+                ("ReLU", &[x], _) => neg_zero[x as usize],
+                _ => true,
+            };
+            if let Some(k) = identity {
+                let (x, grad_x, grad_out) = (args[k], self.grad_args[range.start + k], instr.grad_out);
+                data_slot[out] = x;
+                instr.out = x;
+                report.simplified += 1;
+                if uses[grad_out as usize] == 1 && uses[grad_x as usize] == 1 {
+                    grad_slot[grad_out as usize] = grad_x;
+                } else {
+                    backward.push(i);
+                }
+                continue;
+            }
+
+            let custom = match &instr.kernel {
+                Kernel::Builtin(_) => 0,
+                Kernel::Custom(op) => Ptr::as_ptr(op) as *const () as usize,
+            };
+            let mut key = args.to_vec();
+            if custom == 0 && matches!(instr.op, "+" | "*") {
+                key.sort();
+            }
+            match seen.entry((instr.op, custom, key)) {
+                Entry::Occupied(first) => {
+                    data_slot[out] = *first.get();
+                    instr.out = *first.get();
+                    report.merged += 1;
+                }
+                Entry::Vacant(slot) => {
+                    slot.insert(out as u32);
+                    forward.push(i);
+                }
+            }
+            backward.push(i);
+        }
+
+        self.output = (data_slot[self.output.0 as usize], grad_slot[self.output.1 as usize]);
+        (self.forward, self.backward) = (forward, backward);
+        report.after = self.nodes();
+        report
+    }
+}
+
+impl<T: Float> Debug for Trace<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "Trace(instructions={}, slots={}, inputs={}, params={})",
+            self.forward.len(),
+            self.data.len(),
+            self.inputs.len(),
+            self.params.len()
+        )
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "{} -> {} nodes ({} folded, {} merged, {} simplified)",
+            self.before, self.after, self.folded, self.merged, self.simplified
+        )
+    }
+}
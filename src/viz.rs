@@ -0,0 +1,187 @@
+use crate::engine::{Float, Value};
+use petgraph::{
+    dot::{Config, Dot},
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+use std::{collections::HashMap, fmt::Write};
+
+// Layout constants for the svg output, roughly the sizes graphviz picks for assets/graph.svg
+const RX: f32 = 60.0;
+const RY: f32 = 36.0;
+const X_GAP: f32 = 140.0;
+const Y_GAP: f32 = 110.0;
+const MARGIN: f32 = 8.0;
+
+pub struct Graph<T: Float = f32> {
+    pub graph: DiGraph<Value<T>, &'static str>,
+}
+
+// Walks the graph through prev, edges go from an input to the node it feeds, labeled with that node's op
+pub fn draw_dot<T: Float>(root: &Value<T>) -> Graph<T> {
+    let mut graph = DiGraph::new();
+    let mut index: HashMap<Value<T>, NodeIndex> = HashMap::new();
+    let mut stack = vec![root.clone()];
+    index.insert(root.clone(), graph.add_node(root.clone()));
+
+    while let Some(v) = stack.pop() {
+        let to = index[&v];
+        for child in v.prev.iter() {
+            let from = *index.entry(child.clone()).or_insert_with(|| {
+                stack.push(child.clone());
+                graph.add_node(child.clone())
+            });
+            graph.add_edge(from, to, v.op.unwrap_or(""));
+        }
+    }
+    Graph { graph }
+}
+
+impl<T: Float> Graph<T> {
+    pub fn dot(&self) -> String {
+        format!(
+            "{:?}",
+            Dot::with_attr_getters(
+                &self.graph,
+                &[Config::NodeNoLabel, Config::EdgeNoLabel],
+                &|_, e| format!("label = \"{}\"", e.weight()),
+                &|_, (_, v)| format!("shape = ellipse label = \"{}\"", label(v).join("\\n")),
+            )
+        )
+    }
+
+    pub fn svg(&self) -> String {
+        let (pos, width, height) = self.layout();
+        let mut out = String::new();
+        let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#);
+        let _ = writeln!(
+            out,
+            r#"<svg width="{width:.2}pt" height="{height:.2}pt" viewBox="0.00 0.00 {width:.2} {height:.2}" xmlns="http://www.w3.org/2000/svg">"#
+        );
+        let _ = writeln!(
+            out,
+            r#"<polygon fill="white" stroke="none" points="0,0 {width:.2},0 {width:.2},{height:.2} 0,{height:.2}"/>"#
+        );
+
+        for e in self.graph.edge_references() {
+            let (x1, y1) = pos[e.source().index()];
+            let (x2, y2) = pos[e.target().index()];
+            // Clip the line to both ellipse borders so the arrow head touches the target
+            let (dx, dy) = (x2 - x1, y2 - y1);
+            let len = (dx * dx + dy * dy).sqrt();
+            let t = 1.0 / ((dx / len / RX).powi(2) + (dy / len / RY).powi(2)).sqrt();
+            let (sx, sy) = (x1 + dx / len * t, y1 + dy / len * t);
+            let (ex, ey) = (x2 - dx / len * t, y2 - dy / len * t);
+            let (ux, uy) = (dx / len, dy / len);
+            let (bx, by) = (ex - ux * 10.0, ey - uy * 10.0);
+            let _ = writeln!(out, r#"<g class="edge">"#);
+            let _ = writeln!(out, r#"<path fill="none" stroke="black" d="M{sx:.2},{sy:.2} L{bx:.2},{by:.2}"/>"#);
+            let _ = writeln!(
+                out,
+                r#"<polygon fill="black" stroke="black" points="{ex:.2},{ey:.2} {:.2},{:.2} {:.2},{:.2}"/>"#,
+                bx - uy * 3.5,
+                by + ux * 3.5,
+                bx + uy * 3.5,
+                by - ux * 3.5
+            );
+            let _ = writeln!(
+                out,
+                r#"<text text-anchor="middle" x="{:.2}" y="{:.2}" font-family="Times,serif" font-size="14.00">{}</text>"#,
+                (sx + ex) / 2.0 + 6.0,
+                (sy + ey) / 2.0,
+                escape(e.weight())
+            );
+            let _ = writeln!(out, "</g>");
+        }
+
+        for i in self.graph.node_indices() {
+            let (cx, cy) = pos[i.index()];
+            let lines = label(&self.graph[i]);
+            let _ = writeln!(out, r#"<g class="node">"#);
+            let _ = writeln!(
+                out,
+                r#"<ellipse fill="none" stroke="black" cx="{cx:.2}" cy="{cy:.2}" rx="{RX:.2}" ry="{RY:.2}"/>"#
+            );
+            let top = cy - 8.25 * (lines.len() as f32 - 1.0) + 4.5;
+            for (j, line) in lines.iter().enumerate() {
+                let _ = writeln!(
+                    out,
+                    r#"<text text-anchor="middle" x="{cx:.2}" y="{:.2}" font-family="Times,serif" font-size="14.00">{}</text>"#,
+                    top + 16.5 * j as f32,
+                    escape(line)
+                );
+            }
+            let _ = writeln!(out, "</g>");
+        }
+        let _ = writeln!(out, "</svg>");
+        out
+    }
+
+    // Layered layout: every node sits one row below its deepest input
+    fn layout(&self) -> (Vec<(f32, f32)>, f32, f32) {
+        let n = self.graph.node_count();
+        let mut rank = vec![0usize; n];
+        let mut pending: Vec<usize> = self
+            .graph
+            .node_indices()
+            .map(|i| self.graph.edges_directed(i, Direction::Incoming).count())
+            .collect();
+        let mut ready: Vec<NodeIndex> = self.graph.node_indices().filter(|i| pending[i.index()] == 0).collect();
+        while let Some(i) = ready.pop() {
+            for e in self.graph.edges_directed(i, Direction::Outgoing) {
+                let t = e.target().index();
+                rank[t] = rank[t].max(rank[i.index()] + 1);
+                pending[t] -= 1;
+                if pending[t] == 0 {
+                    ready.push(e.target());
+                }
+            }
+        }
+        // Pull leaves down next to the node they feed, like graphviz does for constants
+        for i in self.graph.node_indices() {
+            if self.graph.edges_directed(i, Direction::Incoming).next().is_none() {
+                if let Some(r) = self.graph.edges_directed(i, Direction::Outgoing).map(|e| rank[e.target().index()]).min() {
+                    rank[i.index()] = r - 1;
+                }
+            }
+        }
+
+        let rows = rank.iter().max().map_or(0, |r| r + 1);
+        let mut columns = vec![0usize; rows];
+        let pos: Vec<(usize, usize)> = (0..n)
+            .map(|i| {
+                let col = columns[rank[i]];
+                columns[rank[i]] += 1;
+                (col, rank[i])
+            })
+            .collect();
+
+        let widest = columns.iter().copied().max().unwrap_or(0) as f32;
+        let width = widest * X_GAP - (X_GAP - 2.0 * RX) + 2.0 * MARGIN;
+        let height = rows as f32 * Y_GAP - (Y_GAP - 2.0 * RY) + 2.0 * MARGIN;
+        let pos = pos
+            .into_iter()
+            .map(|(col, row)| {
+                // Center each row horizontally
+                let offset = (widest - columns[row] as f32) * X_GAP / 2.0;
+                (MARGIN + RX + offset + col as f32 * X_GAP, MARGIN + RY + row as f32 * Y_GAP)
+            })
+            .collect();
+        (pos, width, height)
+    }
+}
+
+fn label<T: Float>(v: &Value<T>) -> Vec<String> {
+    let mut lines = vec![];
+    if let Some(op) = v.op {
+        lines.push(op.to_string());
+    }
+    lines.push(format!("data {:.4}", v.data()));
+    lines.push(format!("grad {:.4}", v.grad()));
+    lines
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
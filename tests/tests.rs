@@ -6,6 +6,11 @@ This is a test to make sure that the tests in the examples pass.
 
 use micrograd::engine::{Activations, Value};
 use micrograd::nn::{mlp, Layer};
+use micrograd::stats::CountingAlloc;
+
+// So graph_stats can check the allocation counter
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
 
 #[test]
 pub fn test_usage() {
@@ -32,6 +37,7 @@ pub fn test_usage() {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn train() {
     // Variables
     mlp!(4);
@@ -99,6 +105,7 @@ fn make_moons() {
     let model: MLP<2, 16, 16, 1> = MLP::new(Activations::Relu, Activations::Relu, Activations::Linear);
     //let model = mlp!(2, 16, 16, 1);
 
+    #[allow(clippy::needless_borrow, clippy::useless_conversion, clippy::let_and_return)]
     fn loss(xs: &[[f32; 2]], ys: &[f32], model: &MLP<2, 16, 16, 1>) -> Value {
         let inputs: Vec<Vec<Value>> = xs.iter().map(|xrow| vec![Value::from(xrow[0]), Value::from(xrow[1])]).collect();
 
@@ -147,3 +154,971 @@ fn make_moons() {
     }
     assert!(total_loss.data() < 0.20);
 }
+
+#[test]
+fn draw_dot() {
+    let x = Value::from(1.0);
+    let y = (&x * &Value::from(2.0) + Value::from(1.0)).relu();
+    y.backward();
+
+    let graph = micrograd::viz::draw_dot(&y);
+    assert_eq!(graph.graph.node_count(), 6);
+    assert_eq!(graph.graph.edge_count(), 5);
+
+    let dot = graph.dot();
+    assert!(dot.starts_with("digraph"));
+    assert!(dot.contains(r"ReLU\ndata 3.0000\ngrad 1.0000"));
+    assert!(dot.contains(r"data 1.0000\ngrad 2.0000"));
+
+    let svg = graph.svg();
+    assert!(svg.contains("<svg"));
+    assert_eq!(svg.matches("<ellipse").count(), 6);
+    assert!(svg.contains(">ReLU</text>"));
+}
+
+#[test]
+fn deep_graph() {
+    // Recursing once per node would overflow the 2MB test thread stack long before this
+    let x = Value::from(1.0);
+    let mut y = Value::from(0.0);
+    for _ in 0..1_000_000 {
+        y = &y + &x;
+    }
+    y.backward();
+    assert_eq!(y.data(), 1_000_000.0);
+    assert_eq!(x.grad(), 1_000_000.0);
+}
+
+#[test]
+fn double_precision() {
+    // Integer literals still give the default f32 Value
+    let y = (Value::from(3) * Value::from(2) + Value::from(1u8)).relu();
+    assert_eq!(y.data(), 7.0f32);
+
+    let x: Value<f64> = Value::from(0.7);
+    let y = &(&x * &x.tanh()).exp() / &x;
+    y.backward();
+    // d/dx exp(x tanh x) / x, evaluated in f64
+    let (t, e) = (0.7f64.tanh(), (0.7 * 0.7f64.tanh()).exp());
+    let expected = e * (t + 0.7 * (1.0 - t * t)) / 0.7 - e / (0.7 * 0.7);
+    assert!((x.grad() - expected).abs() < 1e-12);
+
+    mlp!(3);
+    let model: MLP<3, 4, 1, f64> = MLP::new(Activations::Tanh, Activations::Linear);
+    let out = model.forward(&[Value::from(1.0), Value::from(-2.0), Value::from(0.5)]);
+    out[0].backward();
+    assert_eq!(model.parameters().count(), 21);
+    assert!(model.parameters().all(|p| p.grad().is_finite()));
+}
+
+#[cfg(feature = "sync")]
+#[test]
+fn parallel_backward() {
+    let w = Value::from(0.5);
+    let b = Value::from(-0.25);
+    let xs: Vec<f32> = (0..64).map(|i| i as f32 / 16.0).collect();
+    let f = |xs: &[f32]| xs.iter().map(|x| (&w * &Value::from(*x) + b.clone()).tanh()).sum::<Value>();
+
+    f(&xs).backward();
+    let (w_grad, b_grad) = (w.grad(), b.grad());
+    w.zero_grad();
+    b.zero_grad();
+
+    std::thread::scope(|s| {
+        xs.chunks(16).for_each(|chunk| {
+            s.spawn(|| f(chunk).backward());
+        })
+    });
+    assert!((w.grad() - w_grad).abs() < 1e-4);
+    assert!((b.grad() - b_grad).abs() < 1e-4);
+}
+
+#[test]
+fn tape_usage() {
+    let tape: micrograd::tape::Tape = micrograd::tape::Tape::new();
+    let a = tape.var(-4.0);
+    let b = tape.var(2.0);
+
+    let mut c = a + b;
+    let mut d = a * b + b.pow(tape.var(3.0));
+
+    c = tape.var(2.0) * c + tape.var(1.0);
+    c = tape.var(1.0) + tape.var(2.0) * c + (-a);
+    d = d + d * tape.var(2.0) + (b + a).relu();
+    d = d + tape.var(3.0) * d + (b - a).relu();
+
+    let e = c - d;
+    let f = e.pow(tape.var(2.0));
+    let mut g = f / tape.var(2.0);
+    g = g + tape.var(10.0) / f;
+
+    assert_eq!(format!("{:.4}", g.data()), "24.7041");
+    g.backward();
+    assert_eq!(format!("{:.4}", a.grad()), "138.8338");
+    assert_eq!(format!("{:.4}", b.grad()), "645.5773");
+
+    // Reusing the storage keeps the leaves and drops everything built on top of them
+    let capacity = tape.capacity();
+    tape.truncate(2);
+    tape.zero_grad();
+    let h = (a * b).tanh();
+    h.backward();
+    assert_eq!(tape.len(), 4);
+    assert_eq!(tape.capacity(), capacity);
+    assert_eq!(a.grad(), 2.0 * (1.0 - h.data() * h.data()));
+
+    // Indices of another tape point at the wrong nodes, so mixing tapes panics instead of reading them
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let other: micrograd::tape::Tape = micrograd::tape::Tape::new();
+    let x = other.var(3.0);
+    let panics = |f: &dyn Fn() -> f32| catch_unwind(AssertUnwindSafe(f)).is_err();
+    assert!(panics(&|| (b + x).data()) && panics(&|| (b - x).data()) && panics(&|| (b * x).data()));
+    assert!(panics(&|| (b / x).data()) && panics(&|| b.pow(x).data()));
+
+    // c was dropped by the truncate and its index now belongs to a * b, debug builds catch that
+    if cfg!(debug_assertions) {
+        assert!(panics(&|| c.data()) && panics(&|| c.grad()) && panics(&|| (c + a).data()));
+    }
+}
+
+#[test]
+fn no_grad() {
+    use micrograd::engine::{is_grad_enabled, no_grad};
+
+    mlp!(3);
+    let model: MLP<2, 4, 1> = MLP::new(Activations::Tanh, Activations::Linear);
+    let x = [Value::from(0.5), Value::from(-1.5)];
+
+    let y = no_grad(|| {
+        assert!(!is_grad_enabled());
+        model.forward(&x)[0].clone()
+    });
+    assert!(is_grad_enabled());
+    assert!(y.prev.is_empty() && y.op.is_none());
+    assert_eq!(y.data(), model.forward(&x)[0].data());
+
+    // Gradients flow into a but not through the detached copy of b
+    let a = Value::from(3.0);
+    let b = &a * &a;
+    let c = &a * &b.detach();
+    c.backward();
+    assert_eq!(c.data(), 27.0);
+    assert_eq!(a.grad(), 9.0);
+    assert_eq!(b.grad(), 0.0);
+}
+
+// Every differentiable op, as a function of two inputs, shared by the gradient checks below
+type Op = fn(&[Value<f64>]) -> Value<f64>;
+fn ops() -> Vec<(&'static str, Op)> {
+    vec![
+        ("add", |x| &x[0] + &x[1]),
+        ("sub", |x| &x[0] - &x[1]),
+        ("mul", |x| &x[0] * &x[1]),
+        ("div", |x| &x[0] / &x[1]),
+        ("neg", |x| -&x[0]),
+        ("pow", |x| x[0].pow(&x[1])),
+        ("pow_var", |x| x[0].exp().pow(&x[1].sin())),
+        ("powi", |x| x[0].powi(3) + x[1].powi(-2)),
+        ("powf", |x| x[0].powf(2.5)),
+        ("powneg", |x| x[0].powneg()),
+        ("exp", |x| x[0].exp()),
+        ("ln", |x| x[0].ln()),
+        ("tanh", |x| x[0].tanh()),
+        ("relu", |x| x[0].relu() + x[1].relu()),
+        ("sigmoid", |x| x[0].sigmoid() + x[1].sigmoid()),
+        ("leaky_relu", |x| x[0].leaky_relu(0.1) + x[1].leaky_relu(0.1)),
+        ("elu", |x| x[0].elu(1.5) + x[1].elu(1.5)),
+        ("gelu", |x| x[0].gelu() + x[1].gelu()),
+        ("silu", |x| x[0].silu() + x[1].silu()),
+        ("softplus", |x| x[0].softplus() + x[1].softplus()),
+        ("sqrt", |x| x[0].sqrt()),
+        ("abs", |x| x[0].abs() + x[1].abs()),
+        ("sin", |x| x[0].sin() + x[1].sin()),
+        ("cos", |x| x[0].cos() + x[1].cos()),
+        ("tan", |x| x[0].tan() + x[1].tan()),
+        ("log1p", |x| x[0].log1p() + x[1].log1p()),
+        ("expm1", |x| x[0].expm1() + x[1].expm1()),
+        ("max", |x| Value::max(&x[0], &x[1])),
+        ("min", |x| Value::min(&x[0], &x[1])),
+        ("clamp", |x| x[0].clamp(-1.0, 1.0) + x[1].clamp(-1.0, 1.0)),
+        ("sum", |x| Value::sum(x.iter().cloned())),
+        ("select", |x| Value::select(&x[0], &x[0].sin(), &x[1].exp()) + Value::select(&x[1], &x[0], &x[1].tanh())),
+    ]
+}
+
+#[test]
+fn gradcheck() {
+    use micrograd::gradcheck::gradcheck;
+
+    for (name, f) in ops() {
+        let inputs = [Value::from(1.3), Value::from(-0.7)];
+        if let Err(mismatches) = gradcheck(f, &inputs, 1e-6, 1e-6) {
+            panic!("{name}: {mismatches:?}");
+        }
+    }
+
+    let activations = [
+        Activations::Linear,
+        Activations::Relu,
+        Activations::Tanh,
+        Activations::Sigmoid,
+        Activations::LeakyRelu(0.01),
+        Activations::Elu(1.0),
+        Activations::Gelu,
+        Activations::Silu,
+        Activations::Softplus,
+    ];
+    for act in activations {
+        let layer: Layer<3, 4, f64> = Layer::new(act);
+        let f = |x: &[Value<f64>]| layer.forward(&[x[0].clone(), x[1].clone(), x[2].clone()]).into_iter().sum::<Value<f64>>();
+        let inputs = [Value::from(0.4), Value::from(-1.2), Value::from(0.9)];
+        assert_eq!(gradcheck(f, &inputs, 1e-6, 1e-6), Ok(()), "{layer:?}");
+    }
+
+    // A wrong gradient is reported per input
+    let detached = |x: &[Value<f64>]| &x[0] * &x[1].detach();
+    let mismatches = gradcheck(detached, &[Value::from(2.0), Value::from(3.0)], 1e-6, 1e-6).unwrap_err();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].input, 1);
+    assert_eq!(mismatches[0].analytic, 0.0);
+    assert!((mismatches[0].numeric - 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn pow_negative_base() {
+    // Squared error with a negative residual used to give a NaN exponent gradient
+    let (x, y) = (Value::from(1.0), Value::from(4.0));
+    let two = Value::from(2.0);
+    let loss = (&x - &y).pow(&two);
+    loss.backward();
+    assert_eq!(loss.data(), 9.0);
+    assert_eq!(x.grad(), -6.0);
+    assert_eq!(two.grad(), 0.0);
+
+    let zero = Value::from(0.0);
+    zero.pow(&two).backward();
+    assert_eq!(two.grad(), 0.0);
+
+    // An exponent computed in the graph is trained, so it gets the real gradient and NaN isn't hidden
+    let (x, w) = (Value::from(-2.0f32), Value::from(1.5));
+    let e = &w * &Value::from(2.0);
+    x.pow(&e).backward();
+    assert!(w.grad().is_nan());
+    let anomaly = || micrograd::engine::detect_anomaly(|| x.pow(&(&w * &w)).backward());
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(anomaly)).is_err());
+
+    // The tape and forward mode follow the same rule
+    let tape: micrograd::tape::Tape = micrograd::tape::Tape::new();
+    let (b, c) = (tape.var(-2.0), tape.var(2.0));
+    let (p, q) = (tape.var(1.5), tape.var(1.0));
+    b.pow(c).backward();
+    b.pow(p * q).backward();
+    assert_eq!(c.grad(), 0.0);
+    assert!(p.grad().is_nan());
+    let d = micrograd::dual::Dual::new(-2.0f32, 0.0);
+    assert!(!d.pow(&micrograd::dual::Dual::new(2.0, 0.0)).tangent.is_nan());
+    assert!(d.pow(&micrograd::dual::Dual::new(2.0, 1.0)).tangent.is_nan());
+
+    // Constant exponents only have an input to propagate to
+    let (x, y) = (Value::from(1.0), Value::from(4.0));
+    let loss = (&x - &y).powi(2) + (&x - &y).powf(2.0);
+    loss.backward();
+    assert_eq!(loss.data(), 18.0);
+    assert_eq!(x.grad(), -12.0);
+    assert_eq!(y.grad(), 12.0);
+}
+
+#[test]
+fn activations() {
+    let x = [Value::from(-2.0), Value::from(0.0), Value::from(3.0)];
+    let data = |act: Activations| Value::activate(x.clone(), &act).map(|v| format!("{:.4}", v.data()));
+
+    assert_eq!(data(Activations::Sigmoid), ["0.1192", "0.5000", "0.9526"]);
+    assert_eq!(data(Activations::LeakyRelu(0.1)), ["-0.2000", "0.0000", "3.0000"]);
+    assert_eq!(data(Activations::Elu(1.0)), ["-0.8647", "0.0000", "3.0000"]);
+    assert_eq!(data(Activations::Gelu), ["-0.0454", "0.0000", "2.9964"]);
+    assert_eq!(data(Activations::Silu), ["-0.2384", "0.0000", "2.8577"]);
+    assert_eq!(data(Activations::Softplus), ["0.1269", "0.6931", "3.0486"]);
+
+    // Softplus and sigmoid stay finite far out in the tails
+    let big = Value::from(100.0);
+    assert_eq!(big.softplus().data(), 100.0);
+    assert!((-&big).sigmoid().data() < 1e-40);
+
+    let layer: Layer<2, 3> = Layer::new(Activations::LeakyRelu(0.01));
+    assert_eq!(format!("{layer:?}"), "Layer [LeakyReLU, 3]");
+}
+
+#[test]
+fn subgradients() {
+    let zero = Value::from(0.0);
+    zero.abs().backward();
+    assert_eq!(zero.grad(), 0.0);
+
+    // A tie shares the gradient
+    let (a, b) = (Value::from(2.0), Value::from(2.0));
+    (Value::max(&a, &b) + Value::min(&a, &b)).backward();
+    assert_eq!((a.grad(), b.grad()), (1.0, 1.0));
+
+    let x = Value::from(3.0);
+    let y = x.clamp(-1.0, 1.0);
+    y.backward();
+    assert_eq!((y.data(), x.grad()), (1.0, 0.0));
+
+    // A NaN goes through clamp, it isn't inside the range so it gets no gradient
+    let x = Value::from(f64::NAN);
+    let y = x.clamp(-1.0, 1.0);
+    y.backward();
+    assert!(y.data().is_nan());
+    assert_eq!(x.grad(), 0.0);
+
+    // max and min pass over a NaN, so the gradient goes to the input they gave back
+    let (a, b) = (Value::from(f64::NAN), Value::from(2.0));
+    let (hi, lo) = (Value::max(&a, &b), Value::min(&b, &a));
+    (&hi + &lo).backward();
+    assert_eq!((hi.data(), lo.data()), (2.0, 2.0));
+    assert_eq!((a.grad(), b.grad()), (0.0, 2.0));
+}
+
+#[test]
+fn custom_op() {
+    use micrograd::engine::CustomOp;
+    use micrograd::gradcheck::gradcheck;
+
+    struct Hypot;
+    impl CustomOp<f64> for Hypot {
+        fn name(&self) -> &'static str {
+            "hypot"
+        }
+        fn forward(&self, inputs: &[f64]) -> f64 {
+            inputs[0].hypot(inputs[1])
+        }
+        fn backward(&self, inputs: &[f64], out: f64, grad: f64) -> Vec<f64> {
+            vec![inputs[0] / out * grad, inputs[1] / out * grad]
+        }
+    }
+
+    let (a, b) = (Value::from(3.0), Value::from(4.0));
+    let c = Value::apply(Hypot, &[&a, &b]);
+    assert_eq!(c.data(), 5.0);
+    assert_eq!(c.op, Some("hypot"));
+
+    // Its backward only gives numbers, so grad_graph can't go through it
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| c.grad_graph(&[&a]))).unwrap_err();
+    assert_eq!(*err.downcast::<String>().unwrap(), r#"no differentiable backward for op Some("hypot")"#);
+
+    let f = |x: &[Value<f64>]| Value::apply(Hypot, &[&x[0], &x[1].tanh()]) * x[0].clone();
+    assert_eq!(gradcheck(f, &[a, b], 1e-6, 1e-6), Ok(()));
+    assert!(micrograd::viz::draw_dot(&c).dot().contains(r"hypot\ndata 5.0000"));
+}
+
+#[test]
+fn grad_graph() {
+    use micrograd::engine::enable_grad;
+    use micrograd::gradcheck::gradcheck;
+
+    let x = Value::from(2.0);
+    let y = x.powi(3);
+    let dy = &y.grad_graph(&[&x])[0];
+    let d2y = &dy.grad_graph(&[&x])[0];
+    let d3y = &d2y.grad_graph(&[&x])[0];
+    assert_eq!((dy.data(), d2y.data(), d3y.data()), (12.0, 12.0, 6.0));
+
+    // Mixed partials of x^2 y^3
+    let (a, b) = (Value::from(1.5), Value::from(-2.0));
+    let f = &a.powi(2) * &b.powi(3);
+    let [da, db] = f.grad_graph(&[&a, &b]).try_into().unwrap();
+    assert_eq!(da.grad_graph(&[&b])[0].data(), 2.0 * 1.5 * 3.0 * 4.0);
+    assert_eq!(db.grad_graph(&[&a])[0].data(), 2.0 * 1.5 * 3.0 * 4.0);
+
+    // Differentiating the sum of first derivatives checks every second derivative
+    for (name, f) in ops() {
+        let g = |x: &[Value<f64>]| enable_grad(|| f(x).grad_graph(&[&x[0], &x[1]])).into_iter().sum::<Value<f64>>();
+        let inputs = [Value::from(1.3), Value::from(-0.7)];
+        if let Err(mismatches) = gradcheck(g, &inputs, 1e-6, 1e-5) {
+            panic!("{name}: {mismatches:?}");
+        }
+    }
+}
+
+#[test]
+fn dual() {
+    use micrograd::dual::Dual;
+
+    // d/dx exp(x tanh x) / x, same function as double_precision
+    let x = Dual::var(0.7f64);
+    let y = (x * x.tanh()).exp() / x;
+    let v = Value::from(0.7f64);
+    let z = &(&v * &v.tanh()).exp() / &v;
+    z.backward();
+    assert!((y.data - z.data()).abs() < 1e-12);
+    assert!((y.tangent - v.grad()).abs() < 1e-12);
+
+    // A mix of ops against backward, one input direction at a time
+    type DualOp = fn(&[Dual<f64>]) -> Dual<f64>;
+    let cases: Vec<(&str, DualOp, Op)> = vec![
+        ("pow", |x| x[0].pow(&x[1]), |x| x[0].pow(&x[1])),
+        ("powi", |x| x[0].powi(3), |x| x[0].powi(3)),
+        ("powf", |x| x[0].abs().powf(1.5), |x| x[0].abs().powf(1.5)),
+        ("sigmoid", |x| x[1].sigmoid() * x[0].gelu(), |x| &x[1].sigmoid() * &x[0].gelu()),
+        ("silu", |x| x[0].silu() - x[1].softplus(), |x| &x[0].silu() - &x[1].softplus()),
+        ("elu", |x| x[1].elu(0.5) + x[0].leaky_relu(0.1), |x| &x[1].elu(0.5) + &x[0].leaky_relu(0.1)),
+        ("trig", |x| x[0].sin() * x[1].cos() + x[0].tan(), |x| &(&x[0].sin() * &x[1].cos()) + &x[0].tan()),
+        (
+            "log",
+            |x| x[0].ln() + x[1].log1p() - x[1].expm1() + x[0].sqrt(),
+            |x| &(&(&x[0].ln() + &x[1].log1p()) - &x[1].expm1()) + &x[0].sqrt(),
+        ),
+        (
+            "max",
+            |x| Dual::max(&x[0], &x[1]) - Dual::min(&x[0], &x[1]).relu(),
+            |x| &Value::max(&x[0], &x[1]) - &Value::min(&x[0], &x[1]).relu(),
+        ),
+    ];
+    let point = [1.3, -0.7];
+    for (name, fd, fv) in cases {
+        let xs = point.map(Value::from);
+        fv(&xs).backward();
+        for (i, x) in xs.iter().enumerate() {
+            let ds: Vec<Dual<f64>> = (0..2).map(|j| Dual::new(point[j], if i == j { 1.0 } else { 0.0 })).collect();
+            assert!((fd(&ds).tangent - x.grad()).abs() < 1e-12, "{name} input {i}");
+        }
+    }
+
+    // Jacobian-vector product of an MLP equals the directional derivative from backward
+    mlp!(3);
+    let model: MLP<3, 4, 1, f64> = MLP::new(Activations::Gelu, Activations::Tanh);
+    let (x, dir) = ([1.0, -2.0, 0.5], [0.3, -1.0, 2.0]);
+    let jvp = model.forward_dual(&std::array::from_fn(|i| Dual::new(x[i], dir[i])))[0];
+    let inputs = x.map(Value::from);
+    let out = model.forward(&inputs)[0].clone();
+    out.backward();
+    let expected: f64 = inputs.iter().zip(dir).map(|(x, d)| x.grad() * d).sum();
+    assert!((jvp.data - out.data()).abs() < 1e-12);
+    assert!((jvp.tangent - expected).abs() < 1e-12);
+}
+
+#[test]
+fn jacobian() {
+    use micrograd::dual::Dual;
+    use micrograd::jacobian::{hessian, jacobian};
+
+    // Columns against forward mode, each input direction in turn
+    mlp!(3);
+    let model: MLP<3, 4, 2, f64> = MLP::new(Activations::Tanh, Activations::Sigmoid);
+    let x = [0.5, -1.0, 2.0];
+    let inputs = x.map(Value::from);
+    let jac = jacobian(|x| model.forward(x), &inputs);
+    assert_eq!(jac, jacobian(|x| model.forward(x), &inputs));
+    assert!(inputs.iter().all(|x| x.grad() == 0.0));
+
+    // Grads a backward left in the parameters are still there afterwards
+    model.forward(&inputs)[0].backward();
+    let grads = || model.parameters().map(|p| p.grad()).collect::<Vec<_>>();
+    let before = grads();
+    assert!(before.iter().any(|&g| g != 0.0));
+    jacobian(|x| model.forward(x), &inputs);
+    assert_eq!(grads(), before);
+    for j in 0..3 {
+        let col = model.forward_dual(&std::array::from_fn(|i| Dual::new(x[i], if i == j { 1.0 } else { 0.0 })));
+        assert!(jac.iter().zip(col).all(|(row, d)| (row[j] - d.tangent).abs() < 1e-12));
+    }
+
+    // x^2 y^3 + sin(x y)
+    let (a, b): (f64, f64) = (1.5, -2.0);
+    let h = hessian(|x| &(&x[0].powi(2) * &x[1].powi(3)) + &(&x[0] * &x[1]).sin(), &[Value::from(a), Value::from(b)]);
+    let s = (a * b).sin();
+    let expected = [
+        [2.0 * b.powi(3) - b * b * s, 6.0 * a * b * b + (a * b).cos() - a * b * s],
+        [6.0 * a * b * b + (a * b).cos() - a * b * s, 6.0 * a * a * b - a * a * s],
+    ];
+    for i in 0..2 {
+        for j in 0..2 {
+            assert!((h[i][j] - expected[i][j]).abs() < 1e-12, "{i} {j}: {} vs {}", h[i][j], expected[i][j]);
+        }
+    }
+}
+
+#[test]
+fn retain_graph() {
+    use micrograd::cell::Ptr;
+
+    let x = Value::from(0.5);
+    let h = &x * &x;
+    let y = &h.exp() + &h;
+    y.backward();
+    let (gx, gh) = (x.grad(), h.grad());
+    assert_eq!(gh, 0.25f32.exp() + 1.0);
+
+    // A second backward without zeroing pushes the old intermediate grads through again
+    y.backward();
+    assert!(h.grad() > 2.0 * gh);
+    x.zero_grad();
+    y.zero_grad_graph();
+    assert_eq!((x.grad(), h.grad(), y.grad()), (0.0, 0.0, 0.0));
+    y.backward();
+    assert_eq!((x.grad(), h.grad()), (gx, gh));
+
+    // The seed scales every gradient
+    y.zero_grad_graph();
+    y.backward_with(-3.0);
+    assert_eq!((x.grad(), h.grad()), (-3.0 * gx, -3.0 * gh));
+
+    // Freeing gives back a leaf with the same data and grad, h is still held here so it keeps its links
+    assert_eq!(Ptr::strong_count(&x), 3);
+    let y = y.free_graph();
+    assert!(y.op.is_none() && y.prev.is_empty());
+    assert_eq!((y.data(), y.grad()), (0.25f32.exp() + 0.25, -3.0));
+    assert_eq!(h.prev.len(), 2);
+    assert_eq!(Ptr::strong_count(&x), 3);
+    y.backward();
+    assert_eq!((y.grad(), x.grad()), (1.0, -3.0 * gx));
+
+    // Another graph through h still reaches x, and once h goes only the caller holds x
+    h.zero_grad();
+    (&h * &Value::from(2.0)).backward();
+    assert_eq!(x.grad(), -3.0 * gx + 2.0);
+    drop(h);
+    assert_eq!(Ptr::strong_count(&x), 1);
+}
+
+#[test]
+fn detect_anomaly() {
+    use micrograd::engine::{detect_anomaly, is_anomaly_enabled, no_grad};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let message = |f: &dyn Fn()| *catch_unwind(AssertUnwindSafe(|| detect_anomaly(f))).unwrap_err().downcast::<String>().unwrap();
+
+    // Off by default, the NaN just flows through
+    let x = Value::from(-1.0f32);
+    assert!(x.ln().data().is_nan());
+    let forward = message(&|| {
+        let _ = (&x * &Value::from(2.0)).ln();
+    });
+    assert_eq!(forward, "anomaly in forward: log gave NaN from inputs [*(-2)]");
+    // no_grad doesn't build the node but still checks what the op gave
+    let forward = message(&|| {
+        let _ = no_grad(|| x.ln());
+    });
+    assert_eq!(forward, "anomaly in forward: log gave NaN from inputs [leaf(-1)]");
+    assert!(!is_anomaly_enabled());
+
+    // sqrt(0) is fine forward but its gradient is inf
+    let x = Value::from(0.0);
+    let y = (&x.sqrt() + &Value::from(1.0)).tanh();
+    let backward = message(&|| y.backward());
+    assert!(backward.starts_with("anomaly in backward: sqrt gave grad inf to input 0 from inputs [leaf(0)]"));
+    assert!(backward.ends_with("path from the root: tanh -> + -> sqrt"));
+
+    // Finite graphs are left alone
+    let y = detect_anomaly(|| {
+        let y = (&Value::from(2.0) * &Value::from(3.0)).exp();
+        y.backward();
+        y
+    });
+    assert_eq!(y.data(), 6.0f32.exp());
+}
+
+#[test]
+fn hooks() {
+    use micrograd::cell::{Ptr, RefCell};
+
+    // Clipping on a leaf, the clipped value is what ends up in grad
+    let x = Value::from(3.0f32);
+    x.register_hook(|g| g.clamp(-1.0, 1.0));
+    let y = x.powi(2);
+    y.backward();
+    assert_eq!(x.grad(), 1.0);
+
+    // Gradient reversal in the middle of the graph, everything below it sees the flipped sign
+    let x = Value::from(0.5);
+    let h = &x * &Value::from(2.0);
+    h.register_hook(|g| -g);
+    let y = h.tanh();
+    y.backward();
+    assert_eq!(x.grad(), -2.0 * (1.0 - 1.0f32.tanh().powi(2)));
+
+    // Hooks see the final gradient once, after both uses of h have been added up, and chain in order
+    let seen = Ptr::new(RefCell::new(vec![]));
+    let x = Value::from(1.0);
+    let h = &x * &x;
+    let log = seen.clone();
+    h.register_hook(move |g| {
+        log.borrow_mut().push(g);
+        g
+    });
+    h.register_hook(|g| g * 10.0);
+    let y = &h.exp() + &h;
+    y.backward();
+    assert_eq!(*seen.borrow(), vec![1.0f32.exp() + 1.0]);
+    assert_eq!(h.grad(), 10.0 * (1.0f32.exp() + 1.0));
+    assert_eq!(x.grad(), 2.0 * h.grad());
+
+    h.clear_hooks();
+    y.zero_grad_graph();
+    y.backward();
+    assert_eq!(h.grad(), 1.0f32.exp() + 1.0);
+    assert_eq!(seen.borrow().len(), 1);
+
+    // A hook can change the hooks of its own node while backward runs them, this one removes itself
+    let x = Value::from(2.0f32);
+    let h = &x * &x;
+    let node = h.clone();
+    h.register_hook(move |g| {
+        node.clear_hooks();
+        g * 3.0
+    });
+    let y = h.exp();
+    y.backward();
+    assert_eq!(h.grad(), 3.0 * 4.0f32.exp());
+    y.zero_grad_graph();
+    y.backward();
+    assert_eq!(h.grad(), 4.0f32.exp());
+}
+
+#[test]
+fn trace() {
+    use micrograd::trace::Trace;
+
+    // Every op traced at one point and replayed at another, against a graph built at the second point
+    for (name, f) in ops() {
+        let xs = [Value::from(1.3), Value::from(-0.7)];
+        let mut trace = Trace::new(&f(&xs), &[&xs[0], &xs[1]], []);
+        for point in [[0.4, 2.1], [-1.7, 0.9]] {
+            let ys = point.map(Value::from);
+            let y = f(&ys);
+            y.backward();
+            assert_eq!(trace.forward(&point).to_bits(), y.data().to_bits(), "{name}");
+            trace.backward();
+            for (i, x) in ys.iter().enumerate() {
+                assert_eq!(trace.input_grad(i).to_bits(), x.grad().to_bits(), "{name} input {i}");
+            }
+        }
+    }
+
+    // Parameters are read on every forward and their grads accumulate like backward
+    mlp!(4);
+    let model: MLP<3, 4, 4, 1, f64> = MLP::new(Activations::Tanh, Activations::Gelu, Activations::Linear);
+    let loss = |x: &[Value<f64>; 3], y: &Value<f64>| (&model.forward(x)[0] - y).powi(2);
+    let (x, y) = ([Value::from(0.0), Value::from(0.0), Value::from(0.0)], Value::from(0.0));
+    let mut trace = Trace::new(&loss(&x, &y), &[&x[0], &x[1], &x[2], &y], model.parameters());
+    for step in 0..3 {
+        let sample = [0.5 * step as f64, -1.0, 2.0, 1.0];
+        model.parameters().for_each(|p| p.zero_grad());
+        let out = loss(&[Value::from(sample[0]), Value::from(sample[1]), Value::from(sample[2])], &Value::from(sample[3]));
+        out.backward();
+        let grads: Vec<f64> = model.parameters().map(|p| p.grad()).collect();
+
+        model.parameters().for_each(|p| p.zero_grad());
+        assert_eq!(trace.forward(&sample), out.data());
+        trace.backward();
+        assert_eq!(model.parameters().map(|p| p.grad()).collect::<Vec<_>>(), grads);
+        model.parameters().for_each(|p| p.adjust(-0.1));
+    }
+}
+
+#[test]
+fn trace_optimize() {
+    use micrograd::trace::Trace;
+
+    // Optimized replays against plain replays and a graph built at the same point, bit for bit
+    let check = |f: &dyn Fn(&[Value<f64>]) -> Value<f64>, name: &str| {
+        let xs = [Value::from(1.3), Value::from(-0.7)];
+        let mut plain = Trace::new(&f(&xs), &[&xs[0], &xs[1]], []);
+        let mut trace = Trace::new(&f(&xs), &[&xs[0], &xs[1]], []);
+        let report = trace.optimize();
+        assert!(report.after <= report.before, "{name}: {report}");
+        for point in [[0.4, 2.1], [-1.7, 0.9], [0.0, -0.0]] {
+            let ys = point.map(Value::from);
+            let y = f(&ys);
+            y.backward();
+            assert_eq!(plain.forward(&point).to_bits(), y.data().to_bits(), "{name}");
+            assert_eq!(trace.forward(&point).to_bits(), y.data().to_bits(), "{name}");
+            plain.backward();
+            trace.backward();
+            for (i, x) in ys.iter().enumerate() {
+                assert_eq!(plain.input_grad(i).to_bits(), x.grad().to_bits(), "{name} input {i}");
+                assert_eq!(trace.input_grad(i).to_bits(), x.grad().to_bits(), "{name} input {i}");
+            }
+        }
+        report
+    };
+    for (name, f) in ops() {
+        check(&f, name);
+    }
+
+    // Constant subtrees, repeated products and both kinds of identity
+    let f = |x: &[Value<f64>]| {
+        let c = &(&Value::from(2.0) * &Value::from(3.0)).ln() - &Value::from(1.0);
+        let twice = (&(&x[0] * &x[1]) + &(&x[1] * &x[0])).tanh();
+        let once = &(&x[0] * &x[1]).exp() * &Value::from(1.0);
+        let shared = &x[1] * &Value::from(1.0);
+        let sum: Value<f64> = [once, &c * &x[0], twice, shared.clone(), &shared * &x[0]].into_iter().sum();
+        &sum + &Value::from(-0.0)
+    };
+    let report = check(&f, "mixed");
+    assert!(report.after < report.before, "{report}");
+    assert_eq!((report.folded, report.merged, report.simplified), (4, 5, 4), "{report}");
+
+    // x + 0.0 is only dropped when x can't be -0.0, tanh(-0.0) is -0.0 and exp is never
+    let kept = check(&|x| &x[1].tanh() + &Value::from(0.0), "tanh + 0");
+    let dropped = check(&|x| &x[1].exp() + &Value::from(0.0), "exp + 0");
+    assert_eq!((kept.simplified, dropped.simplified), (0, 1));
+
+    // An MLP with a summed loss, a square is never -0.0 so the 0.0 the sum starts from goes away
+    mlp!(4);
+    let model: MLP<3, 4, 4, 1, f64> = MLP::new(Activations::Tanh, Activations::Gelu, Activations::Linear);
+    let loss = |x: &[Value<f64>; 3], y: &Value<f64>| model.forward(x).iter().map(|out| (out - y).powi(2)).sum::<Value<f64>>();
+    let (x, y) = ([Value::from(0.0), Value::from(0.0), Value::from(0.0)], Value::from(0.0));
+    let mut trace = Trace::new(&loss(&x, &y), &[&x[0], &x[1], &x[2], &y], model.parameters());
+    let before = trace.len();
+    let report = trace.optimize();
+    assert!(trace.len() < before && report.simplified > 0, "{report}");
+    for step in 0..3 {
+        let sample = [0.5 * step as f64, -1.0, 2.0, 1.0];
+        model.parameters().for_each(|p| p.zero_grad());
+        let out = loss(&[Value::from(sample[0]), Value::from(sample[1]), Value::from(sample[2])], &Value::from(sample[3]));
+        out.backward();
+        let grads: Vec<u64> = model.parameters().map(|p| p.grad().to_bits()).collect();
+
+        model.parameters().for_each(|p| p.zero_grad());
+        assert_eq!(trace.forward(&sample).to_bits(), out.data().to_bits());
+        trace.backward();
+        assert_eq!(model.parameters().map(|p| p.grad().to_bits()).collect::<Vec<_>>(), grads);
+        model.parameters().for_each(|p| p.adjust(-0.1));
+    }
+}
+
+#[test]
+fn expr() {
+    use micrograd::expr::Printer;
+
+    let x = Value::from(3.0);
+    let y = (&x * &Value::from(2.0) + Value::from(1.0)).relu();
+    assert_eq!(y.to_expr_string(), "relu((3*2)+1)");
+    assert_eq!(y.to_latex(), r"\operatorname{ReLU}\left(3 \cdot 2 + 1\right)");
+    let p = Printer::new().name(&x, "x");
+    assert_eq!(p.infix(&y), "relu((x*2)+1)");
+    assert_eq!(p.derivative(&y, &x), "2");
+    assert_eq!(Printer::new().simplify().infix(&y), "7");
+
+    // Sub and div print as - and /, a sum leaves out the 0 it starts from
+    let (a, b, c) = (Value::from(1.0), Value::from(2.0), Value::from(4.0));
+    let p = Printer::new().name(&a, "a").name(&b, "b").name(&c, "c");
+    let z = (&(&a - &b) / &c).tanh() + [a.clone(), b.clone(), -&c].into_iter().sum::<Value<f64>>() + a.exp().powi(3);
+    assert_eq!(p.infix(&z), "(tanh((a-b)/c)+((a+b)-c))+(exp(a)^3)");
+    assert_eq!(p.latex(&z), r"\tanh\left(\frac{a - b}{c}\right) + a + b - c + \left(e^{a}\right)^{3}");
+
+    // Derivatives come from grad_graph, named inner nodes stay names
+    let h = &a * &b;
+    let q = &h.sin() / &(&c + &Value::from(1.0)).sqrt();
+    let p = p.name(&h, "h");
+    assert_eq!(p.infix(&q), "sin(h)/sqrt(c+1)");
+    assert_eq!(p.derivative(&q, &b), "(cos(h)/sqrt(c+1))*a");
+    assert_eq!(p.derivative_latex(&q, &b), r"\frac{\cos\left(h\right)}{\sqrt{c + 1}} \cdot a");
+    assert_eq!(p.derivative(&q, &Value::from(0.0)), "0");
+}
+
+#[test]
+fn graph_stats() {
+    use micrograd::cell::{Cell, OnceCell};
+
+    // x * x has two edges into the same leaf, the 0 a sum starts from is a leaf too
+    let (a, b) = (Value::from(2.0), Value::from(3.0));
+    let y = [&a * &a, (&a + &b).tanh()].into_iter().sum::<Value<f64>>();
+    let stats = y.graph_stats();
+    assert_eq!((stats.nodes, stats.leaves, stats.edges, stats.depth), (8, 3, 9, 3));
+    assert_eq!(stats.ops.into_iter().collect::<Vec<_>>(), [("*", 1), ("+", 3), ("tanh", 1)]);
+    assert!(stats.heap_bytes >= 8 * size_of::<micrograd::engine::ValueData<f64>>() + 9 * size_of::<Value<f64>>());
+    assert_eq!(a.graph_stats().depth, 0);
+
+    // A custom op and hooks cost a node one pointer over the original layout until it has them, the
+    // OnceLock behind it with sync adds its once state
+    struct Original {
+        _data: Cell<f32>,
+        _grad: Cell<f32>,
+        _op: Option<&'static str>,
+        _prev: Vec<Value>,
+        _backward: Option<fn(&Value)>,
+    }
+    let extra = if cfg!(feature = "sync") { size_of::<OnceCell<Box<u8>>>() } else { size_of::<usize>() };
+    assert_eq!(size_of::<micrograd::engine::ValueData<f32>>(), size_of::<Original>() + extra);
+
+    // Hooks and spare prev capacity are counted
+    let before = y.graph_stats().heap_bytes;
+    y.register_hook(|g| g);
+    assert!(y.graph_stats().heap_bytes > before);
+
+    // Tests share the counter with each other, so only check what this one adds
+    let before = CountingAlloc::count();
+    let buffer = vec![0u8; 4096];
+    let count = CountingAlloc::count() - before;
+    assert!(count.allocations >= 1 && count.bytes >= 4096, "{count}");
+    drop(buffer);
+}
+
+#[test]
+fn serialize() {
+    use micrograd::engine::CustomOp;
+    use micrograd::serialize::{from_json, to_json, LoadError, Registry};
+
+    struct Hypot;
+    impl CustomOp<f64> for Hypot {
+        fn name(&self) -> &'static str {
+            "hypot"
+        }
+        fn forward(&self, inputs: &[f64]) -> f64 {
+            inputs[0].hypot(inputs[1])
+        }
+        fn backward(&self, inputs: &[f64], out: f64, grad: f64) -> Vec<f64> {
+            vec![inputs[0] / out * grad, inputs[1] / out * grad]
+        }
+    }
+
+    // Every op, saved after backward and loaded back with the same data and grads
+    let xs = [Value::from(0.3), Value::from(-1.7)];
+    let y = Value::sum(ops().iter().map(|(_, f)| f(&xs))) * Value::apply(Hypot, &[&xs[0], &xs[1]]);
+    y.backward();
+    let json = to_json(&y);
+    let registry = Registry::new().register(Hypot);
+    let nodes = from_json(&json, &registry).unwrap();
+    assert_eq!(to_json(nodes.last().unwrap()), json);
+
+    // The loaded backward functions give the same gradients again
+    let topo_grads = |root: &Value<f64>| {
+        let nodes = from_json(&to_json(root), &registry).unwrap();
+        nodes.iter().for_each(|v| v.zero_grad());
+        nodes.last().unwrap().backward();
+        nodes.iter().map(|v| v.grad().to_bits()).collect::<Vec<_>>()
+    };
+    assert_eq!(topo_grads(&y), nodes.iter().map(|v| v.grad().to_bits()).collect::<Vec<_>>());
+
+    // Non-finite numbers and -0.0 survive, leaves keep no op
+    let z = &Value::from(f64::INFINITY) * &Value::from(-0.0);
+    let loaded = from_json(&to_json(&z), &registry).unwrap();
+    assert!(loaded[2].data().is_nan() && loaded[1].data().is_sign_negative());
+    assert_eq!((loaded[0].op, loaded[2].op), (None, Some("*")));
+
+    // Errors
+    assert_eq!(from_json::<f64>(&json, &Registry::new()).unwrap_err(), LoadError::UnknownOp("hypot".into()));
+    let line = r#"{"version": 1, "nodes": [{"op": "*", "data": 1, "grad": 0, "prev": [0, 0]}]}"#;
+    assert_eq!(from_json::<f64>(line, &registry).unwrap_err(), LoadError::BadInput { node: 0, input: 0 });
+    let line = r#"{"version": 1, "nodes": [{"op": null, "data": 1, "grad": 0, "prev": []}, {"op": "tanh", "data": 1, "grad": 0, "prev": [0, 0]}]}"#;
+    assert!(matches!(from_json::<f64>(line, &registry), Err(LoadError::Arity { node: 1, expected: 1, found: 2, .. })));
+    assert_eq!(from_json::<f64>(r#"{"version": 2, "nodes": []}"#, &registry).unwrap_err(), LoadError::Version("2".into()));
+    assert_eq!(from_json::<f64>(r#"{"version": 1, "nodes": [}"#, &registry).unwrap_err(), LoadError::Syntax(25, "a value"));
+
+    // Nesting too deep to follow is an error instead of a stack overflow, as deep as allowed still parses
+    let deep = "[".repeat(200_000);
+    assert_eq!(from_json::<f32>(&deep, &Default::default()).unwrap_err(), LoadError::Syntax(128, "at most 128 levels of nesting"));
+    let deep = format!(r#"{{"version": 1, "nodes": [], "x": {}{}}}"#, "[".repeat(127), "]".repeat(127));
+    assert!(from_json::<f32>(&deep, &Default::default()).unwrap().is_empty());
+}
+
+#[test]
+fn clip_grad() {
+    use micrograd::nn::{clip_grad_norm, clip_grad_value};
+
+    let grads = |params: &[Value]| params.iter().map(|p| p.grad()).collect::<Vec<_>>();
+    let params = [Value::from(1.0), Value::from(2.0)];
+    let set = |g: [f32; 2]| params.iter().zip(g).for_each(|(p, g)| *p.grad.borrow_mut() = g);
+
+    // Scaled down to the max norm, direction kept, left alone when already inside
+    set([3.0, -4.0]);
+    assert_eq!(clip_grad_norm(&params, 1.0), 5.0);
+    assert_eq!(grads(&params), [0.6, -0.8]);
+    assert_eq!(clip_grad_norm(&params, 2.0), 1.0);
+    assert_eq!(grads(&params), [0.6, -0.8]);
+    // A norm that isn't finite is returned and nothing is scaled by it
+    set([f32::INFINITY, -4.0]);
+    assert_eq!(clip_grad_norm(&params, 1.0), f32::INFINITY);
+    assert_eq!(grads(&params), [f32::INFINITY, -4.0]);
+    set([f32::NAN, -4.0]);
+    assert!(clip_grad_norm(&params, 1.0).is_nan());
+    assert!(params[0].grad().is_nan() && params[1].grad() == -4.0);
+
+    // Clamped one by one, the norm returned is from before
+    set([3.0, -0.5]);
+    assert_eq!(clip_grad_value(params.iter(), 1.0), 3.0413813);
+    assert_eq!(grads(&params), [1.0, -0.5]);
+    set([f32::NAN, -4.0]);
+    assert!(clip_grad_value(params.iter(), 1.0).is_nan());
+    assert!(params[0].grad().is_nan() && params[1].grad() == -1.0);
+
+    // Any parameter iterator, here an MLP after a loss that blows up
+    mlp!(4);
+    let model: MLP<2, 4, 4, 1> = MLP::new(Activations::Relu, Activations::Relu, Activations::Linear);
+    let loss = model.forward(&[Value::from(100.0), Value::from(-100.0)])[0].powi(2);
+    loss.backward();
+    let norm = clip_grad_norm(model.parameters(), 1.0);
+    let clipped = model.parameters().map(|p| p.grad() * p.grad()).sum::<f32>().sqrt();
+    assert!(norm <= 1.0 || (clipped - 1.0).abs() < 1e-5, "{norm} {clipped}");
+}
+
+#[test]
+fn fallible() {
+    use micrograd::Error;
+
+    let (a, zero) = (Value::from(-2.0), Value::from(0.0));
+    assert_eq!(a.try_ln().unwrap_err(), Error::Domain { op: "ln", input: -2.0 });
+    assert_eq!(a.try_sqrt().unwrap_err().to_string(), "sqrt is not defined for -2");
+    assert_eq!(Value::from(-1.0).try_log1p().unwrap_err(), Error::Domain { op: "log1p", input: -1.0 });
+    assert_eq!(Value::try_div(&a, &zero).unwrap_err(), Error::DivisionByZero);
+    assert_eq!(zero.try_powneg().unwrap_err(), Error::DivisionByZero);
+    // NaN is rejected by every one of them
+    let nan = Value::from(f32::NAN);
+    assert!(nan.try_ln().is_err() && nan.try_sqrt().is_err() && nan.try_log1p().is_err());
+    assert!(matches!(Value::try_div(&Value::from(1.0), &nan), Err(Error::Domain { op: "div", input }) if input.is_nan()));
+    assert!(matches!(nan.try_powneg(), Err(Error::Domain { op: "powneg", .. })));
+
+    // Inside their domain they are the plain ops
+    let b = Value::from(4.0);
+    assert_eq!(b.try_sqrt().unwrap().data(), 2.0);
+    assert_eq!(Value::try_div(&a, &b).unwrap().data(), -0.5);
+    assert_eq!(b.try_ln().unwrap().op, Some("log"));
+
+    mlp!(3);
+    let model: MLP<2, 3, 1> = MLP::new(Activations::Tanh, Activations::Linear);
+    let x = vec![Value::from(1.0), Value::from(2.0)];
+    assert_eq!(model.try_forward(&x).unwrap()[0].data(), model.forward(&[x[0].clone(), x[1].clone()])[0].data());
+    assert_eq!(model.try_forward(&x[..1]).unwrap_err(), Error::InputSize { expected: 2, found: 1 });
+    let layer: Layer<2, 3> = Layer::new(Activations::Relu);
+    assert_eq!(layer.try_forward(&[]).unwrap_err().to_string(), "expected 2 inputs, got 0");
+
+    // A bad saved graph converts into the same error type
+    let load = || -> Result<Value, Error> { Ok(micrograd::serialize::from_json("{}", &Default::default())?.remove(0)) };
+    assert!(matches!(load(), Err(Error::Load(_))));
+}
+
+#[test]
+fn select() {
+    // Piecewise |x| - 1 for x < 0 and x^2 otherwise, the gradient only reaches the branch that was picked
+    let piecewise = |x: &Value<f64>| Value::select(x, &x.powi(2), &(&x.abs() - &Value::from(1.0)));
+    for (x, y, dx) in [(3.0, 9.0, 6.0), (-2.0, 1.0, -1.0), (0.0, -1.0, 0.0)] {
+        let x = Value::from(x);
+        let out = piecewise(&x);
+        out.backward();
+        assert_eq!((out.data(), x.grad()), (y, dx));
+    }
+    let (cond, a, b) = (Value::from(1.0), Value::from(2.0), Value::from(3.0));
+    Value::select(&cond, &a, &b).backward();
+    assert_eq!((cond.grad(), a.grad(), b.grad()), (0.0, 1.0, 0.0));
+
+    // max_of and min_of over an iterator, as a max pool and a hinge loss
+    let xs = [Value::from(0.5), Value::from(-1.0), Value::from(2.0), Value::from(1.5)];
+    let pool = Value::max_of(xs.iter().cloned()).unwrap();
+    pool.backward();
+    assert_eq!(pool.data(), 2.0);
+    assert_eq!(xs.iter().map(|x| x.grad()).collect::<Vec<_>>(), [0.0, 0.0, 1.0, 0.0]);
+    assert_eq!(Value::min_of(xs.iter().cloned()).unwrap().data(), -1.0);
+    assert!(Value::<f32>::max_of([]).is_none());
+    let score = Value::from(0.3);
+    let hinge = Value::max_of([Value::from(0.0), &Value::from(1.0) - &score]).unwrap();
+    hinge.backward();
+    assert_eq!((hinge.data(), score.grad()), (0.7, -1.0));
+
+    // argmax reads data only, first index on a tie, NaN never wins
+    assert_eq!(Value::argmax(&xs), Some(2));
+    assert_eq!(Value::argmax(&[Value::from(f32::NAN), Value::from(1.0), Value::from(1.0)]), Some(1));
+    assert_eq!(Value::<f32>::argmax(&[]), None);
+}